@@ -0,0 +1,106 @@
+// Compares median split and SAH BVHs over the triangles of an OBJ mesh
+//
+// cargo run --release --example bvh_quality -- assets/stanford-bunny.obj
+
+use std::{env, process, time::Instant};
+
+use tracer::{
+    bvh::SplitMethod,
+    material::{Lambertian, Material},
+    mesh::{Mesh, MeshOptions},
+    ray::{Hittable, Interval, Ray},
+    util::random_unit_float,
+    vec::Vector3,
+};
+
+const RAY_COUNT: usize = 1_000_000;
+
+fn main() {
+    let path = env::args()
+        .nth(1)
+        .unwrap_or_else(|| "assets/stanford-bunny.obj".to_string());
+
+    let load = |split_method| {
+        let material = Material::Lambertian(Lambertian::new(Vector3::new(0.5, 0.5, 0.5)));
+        let options = MeshOptions::default()
+            .material(material)
+            .split_method(split_method);
+        Mesh::from_file_with_options(&path, options).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(1);
+        })
+    };
+
+    let mesh = load(SplitMethod::Sah);
+    println!(
+        "{}: {} triangles, {} vertices",
+        path,
+        mesh.triangle_count(),
+        mesh.vertex_count()
+    );
+
+    let rays = random_rays(&mesh);
+
+    for (name, split_method) in [("median", SplitMethod::Median), ("sah", SplitMethod::Sah)] {
+        // Includes parsing the file, which is the same for both
+        let start = Instant::now();
+        let mesh = load(split_method);
+        let load_time = start.elapsed();
+
+        let start = Instant::now();
+        let hits = rays
+            .iter()
+            .filter(|ray| {
+                mesh.hit(ray, &Interval::new(0.001, f64::INFINITY))
+                    .is_some()
+            })
+            .count();
+        let trace_time = start.elapsed();
+
+        let stats = mesh.bvh_stats();
+        println!();
+        println!("{}", name);
+        println!("  load time:         {:.2?}", load_time);
+        println!(
+            "  nodes:             {} ({} leaves)",
+            stats.node_count, stats.leaf_count
+        );
+        println!("  max depth:         {}", stats.max_depth);
+        println!(
+            "  leaf size:         {:.2} average, {} max",
+            stats.average_leaf_size, stats.max_leaf_size
+        );
+        println!("  sah cost:          {:.2}", stats.sah_cost);
+        println!(
+            "  rays/sec:          {:.0} ({} of {} hit)",
+            RAY_COUNT as f64 / trace_time.as_secs_f64(),
+            hits,
+            RAY_COUNT
+        );
+    }
+}
+
+// Rays from points around the mesh towards points inside its bounding box
+fn random_rays(mesh: &Mesh) -> Vec<Ray> {
+    let bounding_box = mesh.bounding_box();
+    let center = bounding_box.centroid();
+    let extent = Vector3::new(
+        bounding_box.axis_interval(0).size(),
+        bounding_box.axis_interval(1).size(),
+        bounding_box.axis_interval(2).size(),
+    );
+    let radius = extent.length();
+
+    (0..RAY_COUNT)
+        .map(|_| {
+            let origin = center + Vector3::random_unit_vector() * radius;
+            let target = center
+                + Vector3::new(
+                    (random_unit_float() - 0.5) * extent.x(),
+                    (random_unit_float() - 0.5) * extent.y(),
+                    (random_unit_float() - 0.5) * extent.z(),
+                );
+            Ray::new(origin, target - origin)
+        })
+        .collect()
+}
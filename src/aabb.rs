@@ -62,7 +62,7 @@ impl AABB {
             0 => self.x,
             1 => self.y,
             2 => self.z,
-            _ => panic!()
+            _ => panic!(),
         }
     }
 
@@ -94,11 +94,28 @@ impl AABB {
         Some(Interval::new(t_min, t_max))
     }
 
+    pub fn centroid(&self) -> Vector3 {
+        Vector3::new(
+            (self.x.min() + self.x.max()) / 2.0,
+            (self.y.min() + self.y.max()) / 2.0,
+            (self.z.min() + self.z.max()) / 2.0,
+        )
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let (x, y, z) = (self.x.size(), self.y.size(), self.z.size());
+        2.0 * (x * y + y * z + z * x)
+    }
+
     pub fn longest_axis(&self) -> i32 {
         if self.x.size() > self.y.size() {
             return if self.x.size() > self.z.size() { 0 } else { 2 };
         }
 
-        return if self.y.size() > self.z.size() { 1 } else { 2 };
+        if self.y.size() > self.z.size() {
+            1
+        } else {
+            2
+        }
     }
 }
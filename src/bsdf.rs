@@ -0,0 +1,180 @@
+use std::ops::BitOr;
+
+use crate::{
+    material::{
+        Conductor, DiffuseBxdf, FuzzyReflectionBxdf, IsotropicBxdf, RoughDielectricBxdf,
+        SpecularDielectricBxdf,
+    },
+    onb::OrthonormalBasis,
+    principled::PrincipledBxdf,
+    vec::{unit_vector, Vector3},
+};
+
+// Kinds of scattering a BSDF can do, or that a sampled direction came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BsdfFlags(u8);
+
+impl BsdfFlags {
+    pub const REFLECTION: Self = Self(1);
+    pub const TRANSMISSION: Self = Self(1 << 1);
+    pub const DIFFUSE: Self = Self(1 << 2);
+    pub const GLOSSY: Self = Self(1 << 3);
+    // Scattering into directions that only sample() can pick and that have no density,
+    // like perfect mirrors
+    pub const SPECULAR: Self = Self(1 << 4);
+
+    pub fn contains(self, flags: Self) -> bool {
+        self.0 & flags.0 == flags.0
+    }
+
+    pub fn is_specular(self) -> bool {
+        self.contains(Self::SPECULAR)
+    }
+
+    // Whether eval() and pdf() describe at least part of the scattering, so light
+    // sampling can be used
+    pub fn is_non_specular(self) -> bool {
+        self.contains(Self::DIFFUSE) || self.contains(Self::GLOSSY)
+    }
+}
+
+impl BitOr for BsdfFlags {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+// A direction picked by sample(), with the BSDF value and the density it was picked with.
+// Specular samples use the probability of picking their lobe as the density.
+#[derive(Debug, Clone, Copy)]
+pub struct BsdfSample {
+    wi: Vector3,
+    f: Vector3,
+    pdf: f64,
+    flags: BsdfFlags,
+}
+
+impl BsdfSample {
+    pub fn new(wi: Vector3, f: Vector3, pdf: f64, flags: BsdfFlags) -> Self {
+        Self { wi, f, pdf, flags }
+    }
+
+    pub fn wi(&self) -> Vector3 {
+        self.wi
+    }
+
+    pub fn f(&self) -> Vector3 {
+        self.f
+    }
+
+    pub fn pdf(&self) -> f64 {
+        self.pdf
+    }
+
+    pub fn flags(&self) -> BsdfFlags {
+        self.flags
+    }
+}
+
+// Scattering in the local shading frame, where the normal is +z and both directions
+// point away from the surface, wo back along the path. BSDF values include the cosine
+// of wi with the normal, so the phase functions of media fit the same interface.
+pub trait Scatterable {
+    fn sample(&self, wo: Vector3) -> Option<BsdfSample>;
+
+    // Zero for specular scattering
+    fn eval(&self, wo: Vector3, wi: Vector3) -> Vector3;
+
+    // Density with which sample() picks wi, zero for specular scattering
+    fn pdf(&self, wo: Vector3, wi: Vector3) -> f64;
+
+    fn flags(&self) -> BsdfFlags;
+}
+
+// A material's scattering at one hit, with its textures looked up, and the shading frame
+// to move directions into and out of
+#[derive(Debug, Clone)]
+pub struct Bsdf {
+    frame: OrthonormalBasis,
+    bxdf: Bxdf,
+}
+
+impl Bsdf {
+    pub fn new(normal: Vector3, bxdf: Bxdf) -> Self {
+        Self {
+            frame: OrthonormalBasis::new(normal),
+            bxdf,
+        }
+    }
+
+    pub fn to_local(&self, direction: Vector3) -> Vector3 {
+        self.frame.to_local(unit_vector(direction))
+    }
+
+    pub fn to_world(&self, direction: Vector3) -> Vector3 {
+        self.frame.transform(direction)
+    }
+}
+
+impl Scatterable for Bsdf {
+    fn sample(&self, wo: Vector3) -> Option<BsdfSample> {
+        self.bxdf.sample(wo)
+    }
+
+    fn eval(&self, wo: Vector3, wi: Vector3) -> Vector3 {
+        self.bxdf.eval(wo, wi)
+    }
+
+    fn pdf(&self, wo: Vector3, wi: Vector3) -> f64 {
+        self.bxdf.pdf(wo, wi)
+    }
+
+    fn flags(&self) -> BsdfFlags {
+        self.bxdf.flags()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Bxdf {
+    Diffuse(DiffuseBxdf),
+    FuzzyReflection(FuzzyReflectionBxdf),
+    SpecularDielectric(SpecularDielectricBxdf),
+    Conductor(Conductor),
+    RoughDielectric(RoughDielectricBxdf),
+    Principled(PrincipledBxdf),
+    Isotropic(IsotropicBxdf),
+}
+
+impl Bxdf {
+    fn scatterable(&self) -> &dyn Scatterable {
+        match self {
+            Bxdf::Diffuse(diffuse) => diffuse,
+            Bxdf::FuzzyReflection(reflection) => reflection,
+            Bxdf::SpecularDielectric(dielectric) => dielectric,
+            Bxdf::Conductor(conductor) => conductor,
+            Bxdf::RoughDielectric(dielectric) => dielectric,
+            Bxdf::Principled(principled) => principled,
+            Bxdf::Isotropic(isotropic) => isotropic,
+        }
+    }
+}
+
+impl Scatterable for Bxdf {
+    fn sample(&self, wo: Vector3) -> Option<BsdfSample> {
+        self.scatterable().sample(wo)
+    }
+
+    fn eval(&self, wo: Vector3, wi: Vector3) -> Vector3 {
+        self.scatterable().eval(wo, wi)
+    }
+
+    fn pdf(&self, wo: Vector3, wi: Vector3) -> f64 {
+        self.scatterable().pdf(wo, wi)
+    }
+
+    fn flags(&self) -> BsdfFlags {
+        self.scatterable().flags()
+    }
+}
@@ -3,109 +3,425 @@ use std::sync::Arc;
 use crate::{
     aabb::AABB,
     ray::{HitRecord, Hittable, Interval, Ray, WorldObject},
+    vec::Vector3,
 };
 
-// Bounding volume hierarchy
-pub struct BVHNode {
-    // TODO: This turned out very complex, look into refactoring?
-    left: Arc<WorldObject>,
-    right: Arc<WorldObject>,
+// Relative costs of a traversal step and a primitive intersection for the SAH
+const TRAVERSAL_COST: f64 = 1.0;
+const INTERSECTION_COST: f64 = 1.0;
+const BIN_COUNT: usize = 16;
+const MAX_LEAF_SIZE: usize = 4;
+// Below this many primitives subtrees are built on the current thread
+const PARALLEL_THRESHOLD: usize = 4096;
+// Past this depth the median split takes over, which keeps the tree shallow
+// enough for the fixed traversal stack
+const SAH_MAX_DEPTH: usize = 64;
+const STACK_SIZE: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplitMethod {
+    // Longest axis at the median centroid, leaves hold at most two objects
+    Median,
+    // Binned surface area heuristic, leaves hold as many objects as is cheapest
+    Sah,
+}
+
+// Bounding volume hierarchy, flattened into one array in depth-first order so
+// the first child of a node directly follows it
+pub struct BVH<T = Arc<WorldObject>> {
+    nodes: Vec<LinearNode>,
+    // Ordered so every leaf covers a contiguous range
+    primitives: Vec<T>,
+}
+
+struct LinearNode {
     bounding_box: AABB,
-    single: bool,
+    // Leaf: index of the first primitive, interior: index of the second child
+    offset: u32,
+    // Zero for interior nodes
+    primitive_count: u16,
+    // Split axis, decides which child is visited first
+    axis: u8,
 }
 
-impl BVHNode {
-    pub fn new_single_leaf(object: Arc<WorldObject>) -> Self {
-        let left = object.clone();
-        let right = object.clone();
-        let bounding_box = object.bounding_box();
-        Self {
-            left,
-            right,
-            bounding_box,
-            single: true,
-        }
+#[derive(Debug, Clone, Copy)]
+pub struct BVHStats {
+    pub node_count: usize,
+    pub leaf_count: usize,
+    pub max_depth: usize,
+    pub max_leaf_size: usize,
+    pub average_leaf_size: f64,
+    // Expected cost of a ray that hits the root, lower is better
+    pub sah_cost: f64,
+}
+
+// Primitive bounds and centroid, cached so sorting and binning don't recompute them
+struct BuildPrimitive {
+    index: usize,
+    bounding_box: AABB,
+    centroid: Vector3,
+}
+
+// Temporary pointer-based tree, subtrees are built in parallel and flattened afterwards
+struct BuildNode {
+    bounding_box: AABB,
+    contents: BuildContents,
+}
+
+enum BuildContents {
+    Leaf(Vec<usize>),
+    Branch(i32, Box<BuildNode>, Box<BuildNode>),
+}
+
+impl<T: Hittable> BVH<T> {
+    pub fn new(primitives: Vec<T>) -> Self {
+        Self::with_split_method(primitives, SplitMethod::Sah)
     }
 
-    pub fn new_leaf(left: Arc<WorldObject>, right: Arc<WorldObject>) -> Self {
-        let bounding_box = AABB::from_bounding_boxes(left.bounding_box(), right.bounding_box());
+    pub fn with_split_method(primitives: Vec<T>, split_method: SplitMethod) -> Self {
+        let bounding_boxes = primitives.iter().map(|p| p.bounding_box()).collect();
+        Self::from_bounding_boxes(primitives, bounding_boxes, split_method)
+    }
+}
 
-        Self {
-            left,
-            right,
-            bounding_box,
-            single: false,
-        }
+impl<T> BVH<T> {
+    // For primitives that can't be intersected on their own, like the triangle
+    // indices of a mesh. Traverse these with hit_with().
+    pub fn from_bounding_boxes(
+        primitives: Vec<T>,
+        bounding_boxes: Vec<AABB>,
+        split_method: SplitMethod,
+    ) -> Self {
+        assert!(!primitives.is_empty(), "a BVH needs at least one object");
+        assert_eq!(
+            primitives.len(),
+            bounding_boxes.len(),
+            "every primitive needs a bounding box"
+        );
+
+        let mut build_primitives: Vec<BuildPrimitive> = bounding_boxes
+            .into_iter()
+            .enumerate()
+            .map(|(index, bounding_box)| BuildPrimitive {
+                index,
+                bounding_box,
+                centroid: bounding_box.centroid(),
+            })
+            .collect();
+
+        let root = build(&mut build_primitives, split_method, 0);
+
+        let mut nodes = Vec::with_capacity(2 * primitives.len());
+        let mut order = Vec::with_capacity(primitives.len());
+        flatten(root, &mut nodes, &mut order);
+
+        // Move the primitives into leaf order
+        let mut slots: Vec<Option<T>> = primitives.into_iter().map(Some).collect();
+        let primitives = order
+            .into_iter()
+            .map(|index| slots[index].take().unwrap())
+            .collect();
+
+        Self { nodes, primitives }
     }
 
-    pub fn new(objects: Vec<Arc<WorldObject>>) -> Self {
-        if objects.len() == 1 {
-            return BVHNode::new_single_leaf(objects[0].clone());
-        } else if objects.len() == 2 {
-            return BVHNode::new_leaf(objects[0].clone(), objects[1].clone());
-        }
+    // Finds the closest hit, intersecting primitives with the given function
+    pub fn hit_with<'a>(
+        &'a self,
+        ray: &Ray,
+        t: &Interval,
+        mut hit_primitive: impl FnMut(&'a T, &Interval) -> Option<HitRecord<'a>>,
+    ) -> Option<HitRecord<'a>> {
+        let direction = ray.direction();
+        let direction_negative = [
+            direction.x() < 0.0,
+            direction.y() < 0.0,
+            direction.z() < 0.0,
+        ];
+
+        let mut closest: Option<HitRecord> = None;
+        let mut interval = *t;
+
+        let mut stack = [0; STACK_SIZE];
+        let mut stack_length = 0;
+        let mut index = 0;
+
+        loop {
+            let node = &self.nodes[index];
 
-        let mut bounding_box = AABB::empty();
-        for object in &objects {
-            bounding_box = AABB::from_bounding_boxes(bounding_box, object.bounding_box());
+            if node.bounding_box.hit(ray, &interval).is_some() {
+                if node.primitive_count > 0 {
+                    let start = node.offset as usize;
+                    let end = start + node.primitive_count as usize;
+                    for primitive in &self.primitives[start..end] {
+                        if let Some(hit) = hit_primitive(primitive, &interval) {
+                            interval = Interval::new(interval.min(), hit.t());
+                            closest = Some(hit);
+                        }
+                    }
+                } else {
+                    // Visit the child nearer along the split axis first, so the
+                    // farther one is more likely to be culled by a closer hit
+                    let (near, far) = if direction_negative[node.axis as usize] {
+                        (node.offset as usize, index + 1)
+                    } else {
+                        (index + 1, node.offset as usize)
+                    };
+                    stack[stack_length] = far;
+                    stack_length += 1;
+                    index = near;
+                    continue;
+                }
+            }
+
+            if stack_length == 0 {
+                break;
+            }
+            stack_length -= 1;
+            index = stack[stack_length];
         }
 
-        let sort_axis = bounding_box.longest_axis();
+        closest
+    }
 
-        let mut copied_objects = objects.clone();
-        copied_objects.sort_by(|a, b| {
-            let a_axis_interval = a.bounding_box().axis_interval(sort_axis);
-            let b_axis_interval = b.bounding_box().axis_interval(sort_axis);
+    pub fn primitives(&self) -> &[T] {
+        &self.primitives
+    }
+
+    pub fn stats(&self) -> BVHStats {
+        let mut stats = BVHStats {
+            node_count: self.nodes.len(),
+            leaf_count: 0,
+            max_depth: 0,
+            max_leaf_size: 0,
+            average_leaf_size: 0.0,
+            sah_cost: 0.0,
+        };
+        let root_area = self.nodes[0].bounding_box.surface_area();
 
-            let a_center = (a_axis_interval.min() + a_axis_interval.max()) / 2.0;
-            let b_center = (b_axis_interval.min() + b_axis_interval.max()) / 2.0;
+        // (node index, depth)
+        let mut stack = vec![(0, 1)];
+        while let Some((index, depth)) = stack.pop() {
+            let node = &self.nodes[index];
+            let area_ratio = node.bounding_box.surface_area() / root_area;
+            stats.max_depth = stats.max_depth.max(depth);
 
-            a_center.partial_cmp(&b_center).unwrap()
+            if node.primitive_count > 0 {
+                let count = node.primitive_count as usize;
+                stats.leaf_count += 1;
+                stats.max_leaf_size = stats.max_leaf_size.max(count);
+                stats.sah_cost += area_ratio * INTERSECTION_COST * count as f64;
+            } else {
+                stats.sah_cost += area_ratio * TRAVERSAL_COST;
+                stack.push((index + 1, depth + 1));
+                stack.push((node.offset as usize, depth + 1));
+            }
+        }
+
+        stats.average_leaf_size = self.primitives.len() as f64 / stats.leaf_count as f64;
+        stats
+    }
+}
+
+fn build(primitives: &mut [BuildPrimitive], split_method: SplitMethod, depth: usize) -> BuildNode {
+    let bounding_box = primitives
+        .iter()
+        .fold(AABB::empty(), |bounding_box, primitive| {
+            AABB::from_bounding_boxes(bounding_box, primitive.bounding_box)
         });
 
-        let middle = copied_objects.len() / 2;
-        let right = Arc::new(WorldObject::BVHNode(BVHNode::new(
-            copied_objects.split_off(middle),
-        )));
-        let left = Arc::new(WorldObject::BVHNode(BVHNode::new(copied_objects)));
+    let split = match split_method {
+        SplitMethod::Sah if depth < SAH_MAX_DEPTH => sah_split(primitives, bounding_box),
+        _ => median_split(primitives, bounding_box),
+    };
 
-        Self {
-            left,
-            right,
+    let Some((axis, middle)) = split else {
+        let indices = primitives.iter().map(|primitive| primitive.index).collect();
+        return BuildNode {
             bounding_box,
-            single: false,
+            contents: BuildContents::Leaf(indices),
+        };
+    };
+
+    let parallel = primitives.len() > PARALLEL_THRESHOLD;
+    let (left, right) = primitives.split_at_mut(middle);
+    let (left, right) = if parallel {
+        rayon::join(
+            || build(left, split_method, depth + 1),
+            || build(right, split_method, depth + 1),
+        )
+    } else {
+        (
+            build(left, split_method, depth + 1),
+            build(right, split_method, depth + 1),
+        )
+    };
+
+    BuildNode {
+        bounding_box,
+        contents: BuildContents::Branch(axis, Box::new(left), Box::new(right)),
+    }
+}
+
+// Appends the subtree in depth-first order, collecting the primitive order of the leaves
+fn flatten(node: BuildNode, nodes: &mut Vec<LinearNode>, order: &mut Vec<usize>) {
+    let index = nodes.len();
+    nodes.push(LinearNode {
+        bounding_box: node.bounding_box,
+        offset: 0,
+        primitive_count: 0,
+        axis: 0,
+    });
+
+    match node.contents {
+        BuildContents::Leaf(indices) => {
+            nodes[index].offset = order.len() as u32;
+            nodes[index].primitive_count = indices.len() as u16;
+            order.extend(indices);
+        }
+        BuildContents::Branch(axis, left, right) => {
+            flatten(*left, nodes, order);
+            nodes[index].offset = nodes.len() as u32;
+            nodes[index].axis = axis as u8;
+            flatten(*right, nodes, order);
         }
     }
 }
 
-impl Hittable for BVHNode {
-    fn bounding_box(&self) -> crate::aabb::AABB {
-        self.bounding_box
+// Partitions around the median centroid on the longest axis, returns the axis and
+// split index or None when the primitives should become a leaf
+fn median_split(primitives: &mut [BuildPrimitive], bounding_box: AABB) -> Option<(i32, usize)> {
+    if primitives.len() <= 2 {
+        return None;
     }
 
-    fn hit(&self, ray: &Ray, t: &Interval) -> Option<HitRecord> {
-        let bbox_hit = self.bounding_box.hit(ray, t);
-        if bbox_hit.is_none() {
-            return None;
+    let axis = bounding_box.longest_axis();
+    let middle = primitives.len() / 2;
+    primitives.select_nth_unstable_by(middle, |a, b| {
+        a.centroid.axis(axis).total_cmp(&b.centroid.axis(axis))
+    });
+
+    Some((axis, middle))
+}
+
+#[derive(Clone, Copy)]
+struct Bin {
+    bounding_box: AABB,
+    count: usize,
+}
+
+// Bins centroids along each axis and picks the plane with the lowest estimated cost,
+// returns the axis and split index or None when a leaf is cheaper
+fn sah_split(primitives: &mut [BuildPrimitive], bounding_box: AABB) -> Option<(i32, usize)> {
+    let count = primitives.len();
+    if count == 1 {
+        return None;
+    }
+
+    let centroid_bounds = primitives.iter().fold(AABB::empty(), |bounds, primitive| {
+        AABB::from_bounding_boxes(
+            bounds,
+            AABB::from_points(primitive.centroid, primitive.centroid),
+        )
+    });
+
+    let leaf_cost = INTERSECTION_COST * count as f64;
+    let parent_area = bounding_box.surface_area();
+
+    // (cost, axis, bin boundary)
+    let mut best: Option<(f64, i32, usize)> = None;
+
+    for axis in 0..=2 {
+        let extent = centroid_bounds.axis_interval(axis);
+        if extent.size() <= 1e-9 {
+            continue;
         }
 
-        let bbox_interval = bbox_hit.unwrap();
-        let left_hit = self.left.hit(ray, &bbox_interval);
+        let mut bins = [Bin {
+            bounding_box: AABB::empty(),
+            count: 0,
+        }; BIN_COUNT];
 
-        if self.single {
-            return left_hit;
+        for primitive in primitives.iter() {
+            let bin = &mut bins[bin_index(primitive.centroid.axis(axis), extent)];
+            bin.bounding_box = AABB::from_bounding_boxes(bin.bounding_box, primitive.bounding_box);
+            bin.count += 1;
         }
 
-        let right_interval = match left_hit {
-            Some(ref left_hit) => Interval::new(bbox_interval.min(), left_hit.t()),
-            None => bbox_interval,
-        };
-        let right_hit = self.right.hit(ray, &right_interval);
+        // Sweep from the right to get the cost of everything above each boundary
+        let mut right_area = [0.0; BIN_COUNT];
+        let mut right_count = [0; BIN_COUNT];
+        let mut accumulated = AABB::empty();
+        let mut accumulated_count = 0;
+        for i in (1..BIN_COUNT).rev() {
+            accumulated = AABB::from_bounding_boxes(accumulated, bins[i].bounding_box);
+            accumulated_count += bins[i].count;
+            right_area[i] = accumulated.surface_area();
+            right_count[i] = accumulated_count;
+        }
+
+        let mut accumulated = AABB::empty();
+        let mut accumulated_count = 0;
+        for boundary in 1..BIN_COUNT {
+            accumulated = AABB::from_bounding_boxes(accumulated, bins[boundary - 1].bounding_box);
+            accumulated_count += bins[boundary - 1].count;
+
+            if accumulated_count == 0 || right_count[boundary] == 0 {
+                continue;
+            }
 
-        if right_hit.is_some() {
-            return right_hit;
+            let cost = TRAVERSAL_COST
+                + INTERSECTION_COST
+                    * (accumulated.surface_area() * accumulated_count as f64
+                        + right_area[boundary] * right_count[boundary] as f64)
+                    / parent_area;
+
+            if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                best = Some((cost, axis, boundary));
+            }
         }
+    }
+
+    match best {
+        Some((cost, axis, boundary)) if cost < leaf_cost || count > MAX_LEAF_SIZE => {
+            let extent = centroid_bounds.axis_interval(axis);
+            let middle = partition(primitives, |primitive| {
+                bin_index(primitive.centroid.axis(axis), extent) < boundary
+            });
+            Some((axis, middle))
+        }
+        // All centroids coincide, nothing to gain from binning so split by count
+        None if count > MAX_LEAF_SIZE => Some((bounding_box.longest_axis(), count / 2)),
+        _ => None,
+    }
+}
+
+fn bin_index(value: f64, extent: Interval) -> usize {
+    let relative = (value - extent.min()) / extent.size();
+    ((relative * BIN_COUNT as f64) as usize).min(BIN_COUNT - 1)
+}
+
+// Moves primitives matching the predicate to the front, returns how many matched
+fn partition(
+    primitives: &mut [BuildPrimitive],
+    predicate: impl Fn(&BuildPrimitive) -> bool,
+) -> usize {
+    let mut first = 0;
+    for i in 0..primitives.len() {
+        if predicate(&primitives[i]) {
+            primitives.swap(first, i);
+            first += 1;
+        }
+    }
+    first
+}
+
+impl<T: Hittable> Hittable for BVH<T> {
+    fn bounding_box(&self) -> AABB {
+        self.nodes[0].bounding_box
+    }
 
-        left_hit
+    fn hit(&self, ray: &Ray, t: &Interval) -> Option<HitRecord<'_>> {
+        self.hit_with(ray, t, |primitive, interval| primitive.hit(ray, interval))
     }
 }
@@ -2,8 +2,10 @@ use rayon::prelude::*;
 use std::time::Instant;
 
 use crate::{
-    ray::{Interval, Ray},
-    util::sample_square,
+    bsdf::{Bsdf, Scatterable},
+    ray::{HitRecord, Interval, Ray},
+    tonemap::ToneMapper,
+    util::{random_unit_float, sample_square},
     vec::{cross_product, random_in_unit_disk, unit_vector, Vector3},
     world::World,
 };
@@ -11,11 +13,23 @@ use crate::{
 pub struct Camera {
     width: u32,
     height: u32,
-    center: Vector3,
+    aspect_ratio: f64,
+    look_from: Vector3,
+    look_at: Vector3,
+    up: Vector3,
+    vertical_fov: f64,
+    focus_distance: f64,
+    defocus_angle: f64,
+    // Rays get random times between these, objects move from time 0 to 1
+    shutter_open: f64,
+    shutter_close: f64,
     samples_per_pixel: u32,
     max_depth: u32,
     background: Vector3,
+    tone_mapper: ToneMapper,
 
+    // Derived from the values above in initialize()
+    center: Vector3,
     pixel_delta_u: Vector3,
     pixel_delta_v: Vector3,
     pixel00_loc: Vector3,
@@ -26,60 +40,42 @@ pub struct Camera {
 }
 
 impl Camera {
-    pub fn new(
-        width: u32,
-        look_from: Vector3,
-        look_at: Vector3,
-        vertical_fov: f64,
-        focus_distance: f64,
-        defocus_angle: f64,
-        samples_per_pixel: u32,
-        background: Vector3,
-    ) -> Self {
-        let aspect_ratio = 16.0 / 9.0;
-        let height = width as f64 / aspect_ratio;
-
-        let camera_up = Vector3::new(0.0, 1.0, 0.0);
-        let camera_center = look_from;
-
-        let theta = vertical_fov.to_radians();
+    pub fn builder() -> CameraBuilder {
+        CameraBuilder::new()
+    }
+
+    fn initialize(&mut self) {
+        let camera_center = self.look_from;
+
+        let theta = self.vertical_fov.to_radians();
         let h = (theta / 2.0).tan();
 
-        let viewport_height = 2.0 * h * focus_distance;
-        let viewport_width = viewport_height * (width as f64 / height as f64);
+        let viewport_height = 2.0 * h * self.focus_distance;
+        let viewport_width = viewport_height * (self.width as f64 / self.height as f64);
 
-        let w = unit_vector(look_from - look_at);
-        let u = unit_vector(cross_product(camera_up, w));
+        let w = unit_vector(self.look_from - self.look_at);
+        let u = unit_vector(cross_product(view_up(self.up, w), w));
         let v = cross_product(w, u);
 
         let viewport_u = u * viewport_width;
         let viewport_v = -v * viewport_height;
 
-        let pixel_delta_u = viewport_u / width as f64;
-        let pixel_delta_v = viewport_v / height;
+        let pixel_delta_u = viewport_u / self.width as f64;
+        let pixel_delta_v = viewport_v / self.height as f64;
 
         let viewport_upper_left =
-            camera_center - (w * focus_distance) - viewport_u / 2.0 - viewport_v / 2.0;
-
-        let pixel00_loc = viewport_upper_left + (pixel_delta_u + pixel_delta_v) * 0.5;
-
-        let defocus_radius = focus_distance * (defocus_angle.to_radians() / 2.0).tan();
-
-        Self {
-            width,
-            height: height as u32,
-            center: camera_center,
-            samples_per_pixel,
-            max_depth: 50,
-            background,
-            pixel00_loc,
-            pixel_delta_u,
-            pixel_delta_v,
-            pixel_samples_scale: 1.0 / samples_per_pixel as f64,
-            defocus_disk_u: u * defocus_radius,
-            defocus_disk_v: v * defocus_radius,
-            defocus: defocus_angle > 0.0,
-        }
+            camera_center - (w * self.focus_distance) - viewport_u / 2.0 - viewport_v / 2.0;
+
+        let defocus_radius = self.focus_distance * (self.defocus_angle.to_radians() / 2.0).tan();
+
+        self.center = camera_center;
+        self.pixel00_loc = viewport_upper_left + (pixel_delta_u + pixel_delta_v) * 0.5;
+        self.pixel_delta_u = pixel_delta_u;
+        self.pixel_delta_v = pixel_delta_v;
+        self.pixel_samples_scale = 1.0 / self.samples_per_pixel as f64;
+        self.defocus_disk_u = u * defocus_radius;
+        self.defocus_disk_v = v * defocus_radius;
+        self.defocus = self.defocus_angle > 0.0;
     }
 
     pub fn width(&self) -> u32 {
@@ -90,35 +86,89 @@ impl Camera {
         self.height
     }
 
+    pub fn aspect_ratio(&self) -> f64 {
+        self.aspect_ratio
+    }
+
+    pub fn samples_per_pixel(&self) -> u32 {
+        self.samples_per_pixel
+    }
+
+    pub fn max_depth(&self) -> u32 {
+        self.max_depth
+    }
+
+    // Changing the width keeps the aspect ratio and field of view
+    pub fn set_width(&mut self, width: u32) {
+        self.width = width.max(1);
+        self.height = height_for(self.width, self.aspect_ratio);
+        self.initialize();
+    }
+
+    // Changing the height keeps the aspect ratio and field of view
+    pub fn set_height(&mut self, height: u32) {
+        self.height = height.max(1);
+        self.width = ((self.height as f64 * self.aspect_ratio) as u32).max(1);
+        self.initialize();
+    }
+
+    // Sets an explicit resolution, the aspect ratio follows from it
+    pub fn set_resolution(&mut self, width: u32, height: u32) {
+        self.width = width.max(1);
+        self.height = height.max(1);
+        self.aspect_ratio = self.width as f64 / self.height as f64;
+        self.initialize();
+    }
+
+    pub fn set_samples_per_pixel(&mut self, samples_per_pixel: u32) {
+        self.samples_per_pixel = samples_per_pixel;
+        self.initialize();
+    }
+
+    pub fn set_max_depth(&mut self, max_depth: u32) {
+        self.max_depth = max_depth;
+    }
+
+    pub fn set_background(&mut self, background: Vector3) {
+        self.background = background;
+    }
+
+    pub fn tone_mapper(&self) -> ToneMapper {
+        self.tone_mapper
+    }
+
+    pub fn set_tone_mapper(&mut self, tone_mapper: ToneMapper) {
+        self.tone_mapper = tone_mapper;
+    }
+
     pub fn render(&self, world: &World, frame_buffer: &mut [u32]) {
+        let mut hdr_buffer = vec![Vector3::zero(); frame_buffer.len()];
+        self.render_hdr(world, &mut hdr_buffer);
+
+        for (pixel, color) in frame_buffer.iter_mut().zip(hdr_buffer) {
+            *pixel = self.tone_mapper.to_pixel(color);
+        }
+    }
+
+    // Renders linear radiance without clamping, so values above 1.0 are preserved
+    pub fn render_hdr(&self, world: &World, frame_buffer: &mut [Vector3]) {
         let start = Instant::now();
 
         // One chunk is one row of pixels in the image
-        let chunks: Vec<(usize, &mut [u32])> = frame_buffer
+        let chunks: Vec<(usize, &mut [Vector3])> = frame_buffer
             .chunks_mut(self.width as usize)
             .enumerate()
             .collect();
 
-        let interval = Interval::new(0.0, 1.0);
-
         chunks.into_par_iter().for_each(|(height_index, chunk)| {
             for i in 0..self.width {
                 let mut color = Vector3::zero();
                 for _ in 0..self.samples_per_pixel {
                     let ray = self.get_ray(i, height_index as u32);
-                    color = color + self.ray_color(&world, &ray, self.max_depth);
+                    color = color + self.ray_color(world, &ray, self.max_depth, None);
                 }
 
-                let scaled_color = color * self.pixel_samples_scale;
-
-                let (r, g, b) = (
-                    (interval.clamp(scaled_color.x().sqrt()) * 255.0) as u32,
-                    (interval.clamp(scaled_color.y().sqrt()) * 255.0) as u32,
-                    (interval.clamp(scaled_color.z().sqrt()) * 255.0) as u32,
-                );
-
-                // Pack RGB into 1 u32
-                chunk[i as usize] = (r << 16) | (g << 8) | b
+                chunk[i as usize] = color * self.pixel_samples_scale;
             }
         });
 
@@ -138,11 +188,16 @@ impl Camera {
             self.center
         };
 
-        Ray::new(origin, pixel_sample_center - origin)
+        let time =
+            self.shutter_open + (self.shutter_close - self.shutter_open) * random_unit_float();
+
+        Ray::with_time(origin, pixel_sample_center - origin, time)
     }
 
-    fn ray_color(&self, world: &World, ray: &Ray, depth: u32) -> Vector3 {
-        if depth <= 0 {
+    // `bsdf_pdf` is the density with which a diffuse bounce picked this ray, or None
+    // for camera rays and specular bounces that light sampling can't reproduce.
+    fn ray_color(&self, world: &World, ray: &Ray, depth: u32, bsdf_pdf: Option<f64>) -> Vector3 {
+        if depth == 0 {
             return Vector3::zero();
         }
 
@@ -153,18 +208,103 @@ impl Camera {
             Some(hit) => {
                 let material = hit.material();
 
-                let emitted = material.emitted(hit.point());
+                let mut emitted = material.emitted(hit.point());
 
-                let bounce = material.scatter(ray, &hit);
-                match bounce {
-                    Some((bounce_ray, attenunation)) => {
-                        emitted + self.ray_color(world, &bounce_ray, depth - 1) * attenunation
+                // Lights can also be reached through direct light sampling at the previous
+                // hit, so weight the emission to not count it twice
+                if let Some(bsdf_pdf) = bsdf_pdf {
+                    if material.is_emissive() {
+                        let light_pdf =
+                            world.light_pdf_value(ray.origin(), ray.direction(), ray.time());
+                        emitted = emitted * power_heuristic(bsdf_pdf, light_pdf);
                     }
-                    None => emitted,
+                }
+
+                let Some(bsdf) = material.bsdf(&hit) else {
+                    return emitted;
+                };
+                let wo = bsdf.to_local(-ray.direction());
+
+                let direct = if bsdf.flags().is_non_specular() {
+                    self.sample_direct_light(world, &hit, &bsdf, wo, ray.time())
+                } else {
+                    Vector3::zero()
+                };
+
+                match bsdf.sample(wo).filter(|sample| sample.pdf() > 0.0) {
+                    Some(sample) => {
+                        let bounce_ray =
+                            Ray::with_time(hit.point(), bsdf.to_world(sample.wi()), ray.time());
+                        let bounce_pdf = (!sample.flags().is_specular()).then_some(sample.pdf());
+                        emitted
+                            + direct
+                            + self.ray_color(world, &bounce_ray, depth - 1, bounce_pdf)
+                                * (sample.f() / sample.pdf())
+                    }
+                    None => emitted + direct,
                 }
             }
-            None => self.background,
+            None => match world.environment() {
+                // The environment is sampled directly as well, like the lights
+                Some(environment) => {
+                    let emitted = environment.value(ray.direction());
+                    match bsdf_pdf {
+                        Some(bsdf_pdf) => {
+                            let light_pdf =
+                                world.light_pdf_value(ray.origin(), ray.direction(), ray.time());
+                            emitted * power_heuristic(bsdf_pdf, light_pdf)
+                        }
+                        None => emitted,
+                    }
+                }
+                None => self.background,
+            },
+        }
+    }
+
+    // Next event estimation: traces a shadow ray towards a random light, at the time
+    // of the path that reached the hit
+    fn sample_direct_light(
+        &self,
+        world: &World,
+        hit: &HitRecord,
+        bsdf: &Bsdf,
+        wo: Vector3,
+        time: f64,
+    ) -> Vector3 {
+        let Some(direction) = world.sample_light_direction(hit.point(), time) else {
+            return Vector3::zero();
+        };
+
+        let wi = bsdf.to_local(direction);
+        let f = bsdf.eval(wo, wi);
+        if f.near_zero() {
+            return Vector3::zero();
         }
+
+        let light_pdf = world.light_pdf_value(hit.point(), direction, time);
+        if light_pdf <= 0.0 {
+            return Vector3::zero();
+        }
+
+        let shadow_ray = Ray::with_time(hit.point(), direction, time);
+        let interval = Interval::new(0.001, f64::INFINITY);
+        let (light_hit, transmittance) = world.hit_through_media(&shadow_ray, &interval);
+
+        // Anything that isn't a light blocks the sample, glowing media included
+        let emitted = match light_hit {
+            Some(light_hit) if light_hit.material().is_emissive() => {
+                light_hit.material().emitted(light_hit.point())
+            }
+            Some(_) => return Vector3::zero(),
+            None => match world.environment() {
+                Some(environment) => environment.value(direction),
+                None => return Vector3::zero(),
+            },
+        };
+        let bsdf_pdf = bsdf.pdf(wo, wi);
+
+        emitted * transmittance * f * (power_heuristic(light_pdf, bsdf_pdf) / light_pdf)
     }
 
     fn defocus_disk_sample(&self) -> Vector3 {
@@ -172,3 +312,192 @@ impl Camera {
         self.center + (self.defocus_disk_u * point.x()) + (self.defocus_disk_v * point.y())
     }
 }
+
+pub struct CameraBuilder {
+    width: u32,
+    height: Option<u32>,
+    aspect_ratio: f64,
+    look_from: Vector3,
+    look_at: Vector3,
+    up: Vector3,
+    vertical_fov: f64,
+    focus_distance: f64,
+    defocus_angle: f64,
+    shutter_open: f64,
+    shutter_close: f64,
+    samples_per_pixel: u32,
+    max_depth: u32,
+    background: Vector3,
+    tone_mapper: ToneMapper,
+}
+
+impl CameraBuilder {
+    pub fn new() -> Self {
+        Self {
+            width: 800,
+            height: None,
+            aspect_ratio: 16.0 / 9.0,
+            look_from: Vector3::zero(),
+            look_at: Vector3::new(0.0, 0.0, -1.0),
+            up: Vector3::new(0.0, 1.0, 0.0),
+            vertical_fov: 90.0,
+            focus_distance: 10.0,
+            defocus_angle: 0.0,
+            shutter_open: 0.0,
+            shutter_close: 1.0,
+            samples_per_pixel: 100,
+            max_depth: 50,
+            background: Vector3::zero(),
+            tone_mapper: ToneMapper::default(),
+        }
+    }
+
+    pub fn width(mut self, width: u32) -> Self {
+        self.width = width;
+        self
+    }
+
+    // An explicit height takes precedence over the aspect ratio
+    pub fn height(mut self, height: u32) -> Self {
+        self.height = Some(height);
+        self
+    }
+
+    pub fn aspect_ratio(mut self, aspect_ratio: f64) -> Self {
+        self.aspect_ratio = aspect_ratio;
+        self
+    }
+
+    pub fn look_from(mut self, look_from: Vector3) -> Self {
+        self.look_from = look_from;
+        self
+    }
+
+    pub fn look_at(mut self, look_at: Vector3) -> Self {
+        self.look_at = look_at;
+        self
+    }
+
+    // Rolling the camera is done by tilting the up vector
+    pub fn up(mut self, up: Vector3) -> Self {
+        self.up = up;
+        self
+    }
+
+    pub fn vertical_fov(mut self, vertical_fov: f64) -> Self {
+        self.vertical_fov = vertical_fov;
+        self
+    }
+
+    pub fn focus_distance(mut self, focus_distance: f64) -> Self {
+        self.focus_distance = focus_distance;
+        self
+    }
+
+    pub fn defocus_angle(mut self, defocus_angle: f64) -> Self {
+        self.defocus_angle = defocus_angle;
+        self
+    }
+
+    // The shutter is open between these times, within the motion from time 0 to 1.
+    // Moving objects are only bounded for those times, so build() clamps both into
+    // that range and the opening time to no later than the closing one.
+    pub fn shutter(mut self, open: f64, close: f64) -> Self {
+        self.shutter_open = open;
+        self.shutter_close = close;
+        self
+    }
+
+    pub fn samples_per_pixel(mut self, samples_per_pixel: u32) -> Self {
+        self.samples_per_pixel = samples_per_pixel;
+        self
+    }
+
+    pub fn max_depth(mut self, max_depth: u32) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    pub fn background(mut self, background: Vector3) -> Self {
+        self.background = background;
+        self
+    }
+
+    pub fn tone_mapper(mut self, tone_mapper: ToneMapper) -> Self {
+        self.tone_mapper = tone_mapper;
+        self
+    }
+
+    pub fn build(self) -> Camera {
+        let width = self.width.max(1);
+        let (height, aspect_ratio) = match self.height {
+            Some(height) => (height.max(1), width as f64 / height.max(1) as f64),
+            None => (height_for(width, self.aspect_ratio), self.aspect_ratio),
+        };
+        let shutter_close = self.shutter_close.clamp(0.0, 1.0);
+        let shutter_open = self.shutter_open.clamp(0.0, shutter_close);
+
+        let mut camera = Camera {
+            width,
+            height,
+            aspect_ratio,
+            look_from: self.look_from,
+            look_at: self.look_at,
+            up: self.up,
+            vertical_fov: self.vertical_fov,
+            focus_distance: self.focus_distance,
+            defocus_angle: self.defocus_angle,
+            shutter_open,
+            shutter_close,
+            samples_per_pixel: self.samples_per_pixel,
+            max_depth: self.max_depth,
+            background: self.background,
+            tone_mapper: self.tone_mapper,
+            center: self.look_from,
+            pixel00_loc: Vector3::zero(),
+            pixel_delta_u: Vector3::zero(),
+            pixel_delta_v: Vector3::zero(),
+            pixel_samples_scale: 0.0,
+            defocus_disk_u: Vector3::zero(),
+            defocus_disk_v: Vector3::zero(),
+            defocus: false,
+        };
+        camera.initialize();
+        camera
+    }
+}
+
+impl Default for CameraBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Multiple importance sampling weight for a sample from strategy `a`
+fn power_heuristic(a: f64, b: f64) -> f64 {
+    let (a, b) = (a * a, b * b);
+    if a + b <= 0.0 {
+        return 0.0;
+    }
+    a / (a + b)
+}
+
+fn height_for(width: u32, aspect_ratio: f64) -> u32 {
+    ((width as f64 / aspect_ratio) as u32).max(1)
+}
+
+// Looking along the up vector leaves no plane to build the basis in,
+// so fall back to whichever world axis is furthest from the view direction.
+fn view_up(up: Vector3, w: Vector3) -> Vector3 {
+    if !cross_product(up, w).near_zero() {
+        return up;
+    }
+
+    if w.x().abs() < w.y().abs().min(w.z().abs()) {
+        Vector3::new(1.0, 0.0, 0.0)
+    } else if w.y().abs() < w.z().abs() {
+        Vector3::new(0.0, 1.0, 0.0)
+    } else {
+        Vector3::new(0.0, 0.0, 1.0)
+    }
+}
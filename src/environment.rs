@@ -0,0 +1,211 @@
+use std::{f64::consts::PI, path::Path};
+
+use crate::{
+    error::TracerError,
+    sky::Sky,
+    tonemap::luminance,
+    util::random_unit_float,
+    vec::{unit_vector, Vector3},
+};
+
+// What rays that leave the scene see, lighting it from all around
+pub enum Environment {
+    Map(EnvironmentMap),
+    Sky(Sky),
+}
+
+impl Environment {
+    // Radiance arriving from the given direction
+    pub fn value(&self, direction: Vector3) -> Vector3 {
+        match self {
+            Environment::Map(map) => map.value(direction),
+            Environment::Sky(sky) => sky.value(direction),
+        }
+    }
+
+    pub fn sample_direction(&self) -> Option<Vector3> {
+        match self {
+            Environment::Map(map) => map.sample_direction(),
+            Environment::Sky(sky) => sky.sample_direction(),
+        }
+    }
+
+    // Density of sample_direction() per unit solid angle
+    pub fn pdf_value(&self, direction: Vector3) -> f64 {
+        match self {
+            Environment::Map(map) => map.pdf_value(direction),
+            Environment::Sky(sky) => sky.pdf_value(direction),
+        }
+    }
+}
+
+// Light arriving from every direction, read from an equirectangular (latitude-longitude)
+// image. The image center looks down -z, its top row straight up.
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    pixels: Vec<Vector3>,
+    // Radians the map is turned around the y axis
+    rotation: f64,
+    intensity: f64,
+
+    // Sampling distribution, proportional to each pixel's luminance and solid angle
+    weights: Vec<f64>,
+    // Cumulative weights within each row, normalized to end at 1
+    row_cdfs: Vec<f64>,
+    // Cumulative row weights, normalized to end at 1
+    marginal_cdf: Vec<f64>,
+    total_weight: f64,
+}
+
+impl EnvironmentMap {
+    // Loads a Radiance .hdr or OpenEXR image, or any other format the image crate reads
+    pub fn from_file(path: &Path) -> Result<Self, TracerError> {
+        let image = image::open(path)
+            .map_err(|e| TracerError::Image {
+                path: path.to_path_buf(),
+                source: e,
+            })?
+            .into_rgb32f();
+        let (width, height) = image.dimensions();
+
+        let pixels = image
+            .pixels()
+            .map(|pixel| Vector3::new(pixel[0] as f64, pixel[1] as f64, pixel[2] as f64))
+            .collect();
+
+        Ok(Self::new(width as usize, height as usize, pixels))
+    }
+
+    // Pixels are linear RGB, row-major starting at the top left
+    pub fn new(width: usize, height: usize, pixels: Vec<Vector3>) -> Self {
+        assert_eq!(
+            pixels.len(),
+            width * height,
+            "pixel count does not match image dimensions"
+        );
+
+        // Rows near the poles cover less solid angle, so they are picked less often
+        let weights: Vec<f64> = pixels
+            .iter()
+            .enumerate()
+            .map(|(index, &pixel)| {
+                let theta = PI * ((index / width) as f64 + 0.5) / height as f64;
+                luminance(pixel).max(0.0) * theta.sin()
+            })
+            .collect();
+
+        let mut row_cdfs = Vec::with_capacity(pixels.len());
+        let mut marginal_cdf = Vec::with_capacity(height);
+        let mut total_weight = 0.0;
+        for row in weights.chunks_exact(width) {
+            let row_weight: f64 = row.iter().sum();
+            let mut accumulated = 0.0;
+            for &weight in row {
+                accumulated += weight;
+                row_cdfs.push(if row_weight > 0.0 {
+                    accumulated / row_weight
+                } else {
+                    0.0
+                });
+            }
+
+            total_weight += row_weight;
+            marginal_cdf.push(total_weight);
+        }
+        if total_weight > 0.0 {
+            for value in &mut marginal_cdf {
+                *value /= total_weight;
+            }
+        }
+
+        Self {
+            width,
+            height,
+            pixels,
+            rotation: 0.0,
+            intensity: 1.0,
+            weights,
+            row_cdfs,
+            marginal_cdf,
+            total_weight,
+        }
+    }
+
+    // Turns the map around the y axis, from -z towards +x
+    pub fn rotation(mut self, degrees: f64) -> Self {
+        self.rotation = degrees.to_radians();
+        self
+    }
+
+    pub fn intensity(mut self, intensity: f64) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    // Radiance arriving from the given direction
+    pub fn value(&self, direction: Vector3) -> Vector3 {
+        let (column, row, _) = self.pixel(direction);
+        self.pixels[row * self.width + column] * self.intensity
+    }
+
+    // Picks a pixel by its weight and a direction uniformly within it
+    pub fn sample_direction(&self) -> Option<Vector3> {
+        if self.total_weight <= 0.0 {
+            return None;
+        }
+
+        let row = find_interval(&self.marginal_cdf, random_unit_float());
+        let row_cdf = &self.row_cdfs[row * self.width..(row + 1) * self.width];
+        let column = find_interval(row_cdf, random_unit_float());
+
+        let u = (column as f64 + random_unit_float()) / self.width as f64;
+        let v = (row as f64 + random_unit_float()) / self.height as f64;
+
+        let theta = v * PI;
+        let phi = (u - 0.5) * 2.0 * PI + self.rotation;
+        Some(Vector3::new(
+            theta.sin() * phi.sin(),
+            theta.cos(),
+            -theta.sin() * phi.cos(),
+        ))
+    }
+
+    // Density of sample_direction() per unit solid angle
+    pub fn pdf_value(&self, direction: Vector3) -> f64 {
+        if self.total_weight <= 0.0 {
+            return 0.0;
+        }
+
+        let (column, row, sin_theta) = self.pixel(direction);
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+
+        // Uniform within the pixel in image space, then mapped onto the sphere
+        let image_pdf = self.weights[row * self.width + column] / self.total_weight
+            * (self.width * self.height) as f64;
+        image_pdf / (2.0 * PI * PI * sin_theta)
+    }
+
+    // Column and row of the pixel the direction falls into, and the sine of its polar angle
+    fn pixel(&self, direction: Vector3) -> (usize, usize, f64) {
+        let direction = unit_vector(direction);
+        let theta = direction.y().clamp(-1.0, 1.0).acos();
+        let phi = direction.x().atan2(-direction.z()) - self.rotation;
+
+        let u = (phi / (2.0 * PI) + 0.5).rem_euclid(1.0);
+        let v = theta / PI;
+
+        let column = ((u * self.width as f64) as usize).min(self.width - 1);
+        let row = ((v * self.height as f64) as usize).min(self.height - 1);
+        (column, row, theta.sin())
+    }
+}
+
+// Index of the first entry of a normalized CDF that exceeds the value, skipping
+// entries with zero weight
+fn find_interval(cdf: &[f64], value: f64) -> usize {
+    cdf.partition_point(|&cumulative| cumulative <= value)
+        .min(cdf.len() - 1)
+}
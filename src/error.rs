@@ -0,0 +1,160 @@
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+use image::ImageError;
+
+// Errors from loading scenes and assets
+#[derive(Debug)]
+pub enum TracerError {
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    // Malformed input, lines start at 1. The path is missing for in-memory sources.
+    Parse {
+        path: Option<PathBuf>,
+        line: usize,
+        message: String,
+    },
+    // A face references a vertex attribute that doesn't exist. Binary files have no lines,
+    // the face is counted from 1 instead.
+    IndexOutOfRange {
+        path: PathBuf,
+        line: Option<usize>,
+        face: usize,
+        // What the index refers to, e.g. "position" or "normal"
+        attribute: &'static str,
+        index: i64,
+        count: usize,
+    },
+    // A triangle with zero area, which can't be hit or given a normal. The triangle is
+    // counted from 1 and only reported when there is no line.
+    DegenerateTriangle {
+        path: PathBuf,
+        line: Option<usize>,
+        triangle: usize,
+    },
+    // Malformed binary data or a file in an unsupported format
+    InvalidData {
+        path: PathBuf,
+        message: String,
+    },
+    Image {
+        path: PathBuf,
+        source: ImageError,
+    },
+    Gltf {
+        path: PathBuf,
+        source: gltf::Error,
+    },
+}
+
+impl TracerError {
+    pub(crate) fn io(path: &Path, source: io::Error) -> Self {
+        TracerError::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    pub(crate) fn parse(path: &Path, line: usize, message: impl Into<String>) -> Self {
+        TracerError::Parse {
+            path: Some(path.to_path_buf()),
+            line,
+            message: message.into(),
+        }
+    }
+
+    pub(crate) fn invalid_data(path: &Path, message: impl Into<String>) -> Self {
+        TracerError::InvalidData {
+            path: path.to_path_buf(),
+            message: message.into(),
+        }
+    }
+
+    // Fills in the file of a parse error raised from an in-memory source
+    pub(crate) fn in_file(self, file: &Path) -> Self {
+        match self {
+            TracerError::Parse {
+                path: None,
+                line,
+                message,
+            } => TracerError::Parse {
+                path: Some(file.to_path_buf()),
+                line,
+                message,
+            },
+            error => error,
+        }
+    }
+}
+
+impl fmt::Display for TracerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TracerError::Io { path, source } => {
+                write!(f, "failed to read {}: {}", path.display(), source)
+            }
+            TracerError::Parse {
+                path: Some(path),
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+            TracerError::Parse {
+                path: None,
+                line,
+                message,
+            } => write!(f, "line {}: {}", line, message),
+            TracerError::IndexOutOfRange {
+                path,
+                line,
+                face,
+                attribute,
+                index,
+                count,
+            } => {
+                match line {
+                    Some(line) => write!(f, "{}:{}: ", path.display(), line)?,
+                    None => write!(f, "{}: face {}: ", path.display(), face)?,
+                }
+                write!(
+                    f,
+                    "{} index {} is out of range, there are {}",
+                    attribute, index, count
+                )
+            }
+            TracerError::DegenerateTriangle {
+                path,
+                line: Some(line),
+                ..
+            } => write!(f, "{}:{}: degenerate triangle", path.display(), line),
+            TracerError::DegenerateTriangle {
+                path,
+                line: None,
+                triangle,
+            } => write!(f, "{}: triangle {} is degenerate", path.display(), triangle),
+            TracerError::InvalidData { path, message } => {
+                write!(f, "{}: {}", path.display(), message)
+            }
+            TracerError::Image { path, source } => {
+                write!(f, "failed to load {}: {}", path.display(), source)
+            }
+            TracerError::Gltf { path, source } => {
+                write!(f, "failed to load {}: {}", path.display(), source)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TracerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TracerError::Io { source, .. } => Some(source),
+            TracerError::Image { source, .. } => Some(source),
+            TracerError::Gltf { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,439 @@
+use std::{collections::HashMap, path::Path, sync::Arc};
+
+use gltf::{buffer, camera::Projection, image, mesh::Mode, texture, Node, Primitive};
+
+use crate::{
+    aabb::AABB,
+    camera::Camera,
+    error::TracerError,
+    instance::{Instance, Transform},
+    material::{Lambertian, Light, Material},
+    matrix::Matrix4,
+    mesh::{Corner, Face, Mesh, MeshModel, MeshOptions},
+    principled::Principled,
+    ray::{Hittable, WorldObject},
+    scene::Scene,
+    texture::{ImageTexture, Texture},
+    tonemap::srgb_to_linear,
+    vec::Vector3,
+    world::World,
+};
+
+// Image index, the channel of data textures and the factor decoded images are cached by
+type TextureKey = (usize, Option<usize>, [u64; 3]);
+
+// Vertical field of view of the camera that frames scenes without one
+const DEFAULT_VERTICAL_FOV: f64 = 40.0;
+
+// Loads the default scene of a .gltf or .glb file. Every node with a mesh becomes an
+// instance of its primitives, and the first camera in the file is used for the view.
+pub(crate) fn load_gltf(path: &Path) -> Result<Scene, TracerError> {
+    let (document, buffers, images) = gltf::import(path).map_err(|e| TracerError::Gltf {
+        path: path.to_path_buf(),
+        source: e,
+    })?;
+
+    let mut textures = HashMap::new();
+    let materials: Vec<Material> = document
+        .materials()
+        .map(|material| convert_material(&material, &images, &mut textures))
+        .collect();
+
+    // Primitives are shared by every node that refers to their mesh
+    let meshes = document
+        .meshes()
+        .map(|mesh| {
+            mesh.primitives()
+                .filter_map(|primitive| {
+                    load_primitive(&primitive, &buffers, &materials, path).transpose()
+                })
+                .map(|mesh| mesh.map(|mesh| Arc::new(WorldObject::Mesh(mesh))))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or_else(|| TracerError::invalid_data(path, "the file does not contain a scene"))?;
+
+    let mut nodes = NodeVisitor {
+        meshes: &meshes,
+        objects: vec![],
+        cameras: vec![],
+    };
+    for node in scene.nodes() {
+        nodes.visit(&node, Matrix4::identity());
+    }
+
+    if nodes.objects.is_empty() {
+        return Err(TracerError::invalid_data(
+            path,
+            "the scene does not contain any triangle meshes",
+        ));
+    }
+
+    let bounding_box = nodes
+        .objects
+        .iter()
+        .fold(AABB::empty(), |bounding_box, object| {
+            AABB::from_bounding_boxes(bounding_box, object.bounding_box())
+        });
+    let camera = nodes
+        .cameras
+        .iter()
+        .min_by_key(|(camera, _)| camera.index())
+        .and_then(|(camera, matrix)| convert_camera(camera, *matrix))
+        .unwrap_or_else(|| framing_camera(bounding_box));
+
+    Ok(Scene::new(camera, World::new(nodes.objects)))
+}
+
+// Walks the node hierarchy, accumulating the transforms from the root
+struct NodeVisitor<'a> {
+    meshes: &'a [Vec<Arc<WorldObject>>],
+    objects: Vec<Arc<WorldObject>>,
+    cameras: Vec<(gltf::Camera<'a>, Matrix4)>,
+}
+
+impl<'a> NodeVisitor<'a> {
+    fn visit(&mut self, node: &Node<'a>, parent: Matrix4) {
+        // glTF matrices are column-major
+        let columns = node.transform().matrix();
+        let local = Matrix4::new(std::array::from_fn(|row| {
+            std::array::from_fn(|column| columns[column][row] as f64)
+        }));
+        let matrix = parent * local;
+
+        if let Some(mesh) = node.mesh() {
+            // A singular matrix, e.g. a zero scale, hides the node
+            if let Some(transform) = Transform::new(matrix) {
+                for primitive in &self.meshes[mesh.index()] {
+                    self.objects
+                        .push(Arc::new(WorldObject::Instance(Instance::new(
+                            primitive.clone(),
+                            transform,
+                        ))));
+                }
+            }
+        }
+
+        if let Some(camera) = node.camera() {
+            self.cameras.push((camera, matrix));
+        }
+
+        for child in node.children() {
+            self.visit(&child, matrix);
+        }
+    }
+}
+
+// Returns None for primitives without triangles, such as points and lines
+fn load_primitive(
+    primitive: &Primitive,
+    buffers: &[buffer::Data],
+    materials: &[Material],
+    path: &Path,
+) -> Result<Option<Mesh>, TracerError> {
+    if primitive.mode() != Mode::Triangles {
+        return Ok(None);
+    }
+
+    let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|data| &data.0[..]));
+    let Some(positions) = reader.read_positions() else {
+        return Ok(None);
+    };
+
+    let mut model = MeshModel {
+        positions: positions.map(vector).collect(),
+        ..MeshModel::default()
+    };
+    if let Some(normals) = reader.read_normals() {
+        model.normals = normals.map(vector).collect();
+    }
+    // glTF puts the texture origin at the top left
+    if let Some(coordinates) = reader.read_tex_coords(0) {
+        model.texture_coordinates = coordinates
+            .into_f32()
+            .map(|[u, v]| [u as f64, 1.0 - v as f64])
+            .collect();
+    }
+    if let Some(colors) = reader.read_colors(0) {
+        model.colors = colors.into_rgb_f32().map(vector).collect();
+    }
+
+    let vertex_count = model.positions.len();
+    if [
+        model.normals.len(),
+        model.texture_coordinates.len(),
+        model.colors.len(),
+    ]
+    .iter()
+    .any(|&count| count != 0 && count != vertex_count)
+    {
+        return Err(TracerError::invalid_data(
+            path,
+            "a primitive's vertex attributes have different lengths",
+        ));
+    }
+    let indices: Vec<u32> = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect(),
+        None => (0..vertex_count as u32).collect(),
+    };
+
+    for (number, triangle) in indices.chunks_exact(3).enumerate() {
+        let corners = triangle
+            .iter()
+            .map(|&index| {
+                let index = index as usize;
+                if index >= vertex_count {
+                    return Err(TracerError::IndexOutOfRange {
+                        path: path.to_path_buf(),
+                        line: None,
+                        face: number + 1,
+                        attribute: "vertex",
+                        index: index as i64,
+                        count: vertex_count,
+                    });
+                }
+                Ok(Corner {
+                    position: index,
+                    texture: (!model.texture_coordinates.is_empty()).then_some(index),
+                    normal: (!model.normals.is_empty()).then_some(index),
+                    color: (!model.colors.is_empty()).then_some(index),
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        model.faces.push(Face {
+            corners: [corners[0], corners[1], corners[2]],
+            material: None,
+            line: None,
+        });
+    }
+
+    if model.faces.is_empty() {
+        return Ok(None);
+    }
+
+    let material = match primitive.material().index() {
+        Some(index) => materials[index].clone(),
+        None => Material::Lambertian(Lambertian::new(Vector3::new(0.8, 0.8, 0.8))),
+    };
+    let options = MeshOptions::default().material(material);
+
+    Mesh::from_model(path, model, options).map(Some)
+}
+
+// Maps the metallic-roughness model onto a principled material, with the roughness and
+// metallic values read from their texture and the transmission and refraction index
+// extensions. Emissive surfaces become lights.
+fn convert_material(
+    material: &gltf::Material,
+    images: &[image::Data],
+    textures: &mut HashMap<TextureKey, ImageTexture>,
+) -> Material {
+    let emission =
+        vector(material.emissive_factor()) * material.emissive_strength().unwrap_or(1.0) as f64;
+    if !emission.near_zero() {
+        return Material::Light(Light::new(emission));
+    }
+
+    let image = |info: Option<texture::Info>| {
+        info.map(|info| info.texture().source().index())
+            .filter(|&index| index < images.len())
+    };
+
+    let pbr = material.pbr_metallic_roughness();
+    let [r, g, b, _] = pbr.base_color_factor();
+    let factor = Vector3::new(r as f64, g as f64, b as f64);
+    let base_color = match image(pbr.base_color_texture()) {
+        Some(index) => cached_texture(images, textures, index, None, factor),
+        None => factor.into(),
+    };
+
+    // Roughness is stored in the green channel and metallic in the blue one
+    let metallic_roughness = image(pbr.metallic_roughness_texture());
+    let mut principled = Principled::from_texture(base_color)
+        .metallic(data_texture(
+            images,
+            textures,
+            metallic_roughness,
+            2,
+            pbr.metallic_factor(),
+        ))
+        .roughness(data_texture(
+            images,
+            textures,
+            metallic_roughness,
+            1,
+            pbr.roughness_factor(),
+        ))
+        .refraction_index(material.ior().unwrap_or(1.5) as f64);
+
+    if let Some(transmission) = material.transmission() {
+        let index = image(transmission.transmission_texture());
+        principled = principled.transmission(data_texture(
+            images,
+            textures,
+            index,
+            0,
+            transmission.transmission_factor(),
+        ));
+    }
+
+    Material::Principled(Box::new(principled))
+}
+
+// Decodes an image once for every channel and factor it is used with
+fn cached_texture(
+    images: &[image::Data],
+    textures: &mut HashMap<TextureKey, ImageTexture>,
+    index: usize,
+    channel: Option<usize>,
+    factor: Vector3,
+) -> Texture {
+    let key = (
+        index,
+        channel,
+        [
+            factor.x().to_bits(),
+            factor.y().to_bits(),
+            factor.z().to_bits(),
+        ],
+    );
+    let texture = textures
+        .entry(key)
+        .or_insert_with(|| image_texture(&images[index], channel, factor));
+    Texture::Image(texture.clone())
+}
+
+// One channel of an optional image times the factor, or just the factor without an image
+fn data_texture(
+    images: &[image::Data],
+    textures: &mut HashMap<TextureKey, ImageTexture>,
+    index: Option<usize>,
+    channel: usize,
+    factor: f32,
+) -> Texture {
+    let factor = factor as f64;
+    match index {
+        Some(index) => cached_texture(
+            images,
+            textures,
+            index,
+            Some(channel),
+            Vector3::new(factor, factor, factor),
+        ),
+        None => factor.into(),
+    }
+}
+
+// Converts a decoded image to linear RGB, multiplied by the factor. Data textures pick a
+// single channel, which is not sRGB encoded, and spread it over all three.
+fn image_texture(image: &image::Data, channel: Option<usize>, factor: Vector3) -> ImageTexture {
+    use image::Format;
+
+    // Channels per pixel and bytes per channel
+    let (channels, size) = match image.format {
+        Format::R8 => (1, 1),
+        Format::R8G8 => (2, 1),
+        Format::R8G8B8 => (3, 1),
+        Format::R8G8B8A8 => (4, 1),
+        Format::R16 => (1, 2),
+        Format::R16G16 => (2, 2),
+        Format::R16G16B16 => (3, 2),
+        Format::R16G16B16A16 => (4, 2),
+        Format::R32G32B32FLOAT => (3, 4),
+        Format::R32G32B32A32FLOAT => (4, 4),
+    };
+
+    // Integer color channels are sRGB encoded, floating point ones linear
+    let decode = |value: f64| {
+        if channel.is_none() {
+            srgb_to_linear(value)
+        } else {
+            value
+        }
+    };
+    let value = |bytes: &[u8]| match size {
+        1 => decode(bytes[0] as f64 / u8::MAX as f64),
+        2 => decode(u16::from_ne_bytes([bytes[0], bytes[1]]) as f64 / u16::MAX as f64),
+        _ => f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+    };
+
+    let pixels = image
+        .pixels
+        .chunks_exact(channels * size)
+        .map(|pixel| {
+            if let Some(channel) = channel {
+                let offset = channel.min(channels - 1) * size;
+                let data = value(&pixel[offset..offset + size]);
+                return Vector3::new(data, data, data) * factor;
+            }
+
+            // One or two channels are grey, with the second being alpha
+            let color = if channels < 3 {
+                let grey = value(&pixel[..size]);
+                Vector3::new(grey, grey, grey)
+            } else {
+                Vector3::new(
+                    value(&pixel[..size]),
+                    value(&pixel[size..2 * size]),
+                    value(&pixel[2 * size..3 * size]),
+                )
+            };
+            color * factor
+        })
+        .collect();
+
+    ImageTexture::new(image.width, image.height, pixels)
+}
+
+// glTF cameras look down their local -z axis with y up. Orthographic cameras are not
+// supported and give None.
+fn convert_camera(camera: &gltf::Camera, matrix: Matrix4) -> Option<Camera> {
+    let Projection::Perspective(perspective) = camera.projection() else {
+        return None;
+    };
+
+    let mut builder = Camera::builder()
+        .look_from(matrix.transform_point(Vector3::zero()))
+        .look_at(matrix.transform_point(Vector3::new(0.0, 0.0, -1.0)))
+        .up(matrix.transform_vector(Vector3::new(0.0, 1.0, 0.0)))
+        .vertical_fov((perspective.yfov() as f64).to_degrees())
+        .background(background());
+    if let Some(aspect_ratio) = perspective.aspect_ratio() {
+        builder = builder.aspect_ratio(aspect_ratio as f64);
+    }
+
+    Some(builder.build())
+}
+
+// Looks at the scene from its front, the +z side, far enough to fit its bounding sphere
+fn framing_camera(bounding_box: AABB) -> Camera {
+    let center = bounding_box.centroid();
+    let diagonal = Vector3::new(
+        bounding_box.axis_interval(0).size(),
+        bounding_box.axis_interval(1).size(),
+        bounding_box.axis_interval(2).size(),
+    );
+    let radius = diagonal.length() / 2.0;
+    let distance = radius / (DEFAULT_VERTICAL_FOV.to_radians() / 2.0).sin();
+
+    Camera::builder()
+        .look_from(center + Vector3::new(0.0, 0.0, distance))
+        .look_at(center)
+        .vertical_fov(DEFAULT_VERTICAL_FOV)
+        .background(background())
+        .build()
+}
+
+// glTF doesn't describe a background, so scenes get the same sky as the examples
+fn background() -> Vector3 {
+    Vector3::new(0.7, 0.8, 1.0)
+}
+
+fn vector([x, y, z]: [f32; 3]) -> Vector3 {
+    Vector3::new(x as f64, y as f64, z as f64)
+}
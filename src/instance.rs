@@ -0,0 +1,410 @@
+use std::sync::Arc;
+
+use crate::{
+    aabb::AABB,
+    matrix::Matrix4,
+    quaternion::Quaternion,
+    ray::{HitRecord, Hittable, Interval, Ray, Sampleable, WorldObject},
+    vec::{unit_vector, Vector3},
+};
+
+// Affine transform with its inverse cached, since rays need the inverse and hits the forward matrix
+#[derive(Debug, Clone, Copy)]
+pub struct Transform {
+    matrix: Matrix4,
+    inverse: Matrix4,
+}
+
+impl Transform {
+    // Returns None when the matrix is singular, e.g. a zero scale
+    pub fn new(matrix: Matrix4) -> Option<Self> {
+        let inverse = matrix.inverse()?;
+        Some(Self { matrix, inverse })
+    }
+
+    pub fn identity() -> Self {
+        Self {
+            matrix: Matrix4::identity(),
+            inverse: Matrix4::identity(),
+        }
+    }
+
+    pub fn matrix(&self) -> Matrix4 {
+        self.matrix
+    }
+
+    pub fn inverse(&self) -> Matrix4 {
+        self.inverse
+    }
+
+    // Applies `next` after this transform
+    pub fn then(&self, next: Transform) -> Self {
+        Self {
+            matrix: next.matrix * self.matrix,
+            inverse: self.inverse * next.inverse,
+        }
+    }
+
+    pub fn translate(self, offset: Vector3) -> Self {
+        self.then(Self {
+            matrix: Matrix4::translation(offset),
+            inverse: Matrix4::translation(-offset),
+        })
+    }
+
+    // Panics on a zero scale factor
+    pub fn scale(self, scale: Vector3) -> Self {
+        let inverse = Vector3::new(1.0 / scale.x(), 1.0 / scale.y(), 1.0 / scale.z());
+        assert!(
+            inverse.x().is_finite() && inverse.y().is_finite() && inverse.z().is_finite(),
+            "scale factors must be non-zero"
+        );
+        self.then(Self {
+            matrix: Matrix4::scaling(scale),
+            inverse: Matrix4::scaling(inverse),
+        })
+    }
+
+    pub fn rotate_x(self, degrees: f64) -> Self {
+        self.then(Self {
+            matrix: Matrix4::rotation_x(degrees),
+            inverse: Matrix4::rotation_x(-degrees),
+        })
+    }
+
+    pub fn rotate_y(self, degrees: f64) -> Self {
+        self.then(Self {
+            matrix: Matrix4::rotation_y(degrees),
+            inverse: Matrix4::rotation_y(-degrees),
+        })
+    }
+
+    pub fn rotate_z(self, degrees: f64) -> Self {
+        self.then(Self {
+            matrix: Matrix4::rotation_z(degrees),
+            inverse: Matrix4::rotation_z(-degrees),
+        })
+    }
+
+    pub fn transform_point(&self, point: Vector3) -> Vector3 {
+        self.matrix.transform_point(point)
+    }
+
+    pub fn transform_vector(&self, vector: Vector3) -> Vector3 {
+        self.matrix.transform_vector(vector)
+    }
+
+    // Normals transform by the inverse transpose to stay perpendicular to the surface
+    pub fn transform_normal(&self, normal: Vector3) -> Vector3 {
+        unit_vector(self.inverse.transpose().transform_vector(normal))
+    }
+
+    pub fn transform_bounding_box(&self, bounding_box: AABB) -> AABB {
+        let mut min = Vector3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max = Vector3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+
+        for corner in corners(bounding_box) {
+            let corner = self.transform_point(corner);
+            min = Vector3::new(
+                min.x().min(corner.x()),
+                min.y().min(corner.y()),
+                min.z().min(corner.z()),
+            );
+            max = Vector3::new(
+                max.x().max(corner.x()),
+                max.y().max(corner.y()),
+                max.z().max(corner.z()),
+            );
+        }
+
+        AABB::from_points(min, max)
+    }
+}
+
+// Places a shared object in the world with an affine transform
+pub struct Instance {
+    object: Arc<WorldObject>,
+    transform: Transform,
+    // Decomposed transforms at times 0 and 1 of a moving instance, `transform` is the one
+    // at time 0. Boxed to keep static instances small.
+    motion: Option<Box<(Decomposed, Decomposed)>>,
+    bounding_box: AABB,
+}
+
+impl Instance {
+    pub fn new(object: Arc<WorldObject>, transform: Transform) -> Self {
+        let bounding_box = transform.transform_bounding_box(object.bounding_box());
+        Self {
+            object,
+            transform,
+            motion: None,
+            bounding_box,
+        }
+    }
+
+    // Moves from `start` at time 0 to `end` at time 1, blending their translations, scales
+    // and rotations separately so the object turns without changing its shape
+    pub fn new_moving(object: Arc<WorldObject>, start: Transform, end: Transform) -> Self {
+        let from = Decomposed::new(start.matrix());
+        let to = Decomposed::new(end.matrix());
+
+        let start_box = start.transform_bounding_box(object.bounding_box());
+        let end_box = end.transform_bounding_box(object.bounding_box());
+        let bounding_box = if from.rotation.dot(&to.rotation).abs() > 1.0 - 1e-9 {
+            // Without a rotation every point moves in a straight line between its two end
+            // positions
+            AABB::from_bounding_boxes(start_box, end_box)
+        } else {
+            // Turning points sweep out arcs, but never get further from the blended
+            // translation than the scaled object's farthest corner
+            let radius = corners(object.bounding_box())
+                .map(|corner| {
+                    multiply(from.stretch, corner)
+                        .length()
+                        .max(multiply(to.stretch, corner).length())
+                })
+                .into_iter()
+                .fold(0.0, f64::max);
+            let extent = Vector3::new(radius, radius, radius);
+            AABB::from_bounding_boxes(
+                AABB::from_points(from.translation - extent, from.translation + extent),
+                AABB::from_points(to.translation - extent, to.translation + extent),
+            )
+        };
+
+        Self {
+            object,
+            transform: start,
+            motion: Some(Box::new((from, to))),
+            bounding_box,
+        }
+    }
+
+    pub fn object(&self) -> &Arc<WorldObject> {
+        &self.object
+    }
+
+    pub fn transform(&self) -> Transform {
+        self.transform
+    }
+
+    // Places another object the same way and with the same motion, such as a light
+    // inside this instance's object
+    pub(crate) fn with_object(&self, object: Arc<WorldObject>) -> Self {
+        match (&self.motion, self.transform_at(1.0)) {
+            (Some(_), Some(end)) => Self::new_moving(object, self.transform, end),
+            _ => Self::new(object, self.transform),
+        }
+    }
+
+    // None when the blended scale is singular
+    fn transform_at(&self, time: f64) -> Option<Transform> {
+        match &self.motion {
+            Some(motion) => {
+                let (from, to) = motion.as_ref();
+                from.lerp(to, time)
+            }
+            None => Some(self.transform),
+        }
+    }
+}
+
+impl Hittable for Instance {
+    fn bounding_box(&self) -> AABB {
+        self.bounding_box
+    }
+
+    fn hit(&self, ray: &Ray, t: &Interval) -> Option<HitRecord<'_>> {
+        let transform = self.transform_at(ray.time())?;
+
+        // The direction is not normalized, so t is the same in both spaces
+        let inverse = transform.inverse();
+        let object_ray = Ray::with_time(
+            inverse.transform_point(ray.origin()),
+            inverse.transform_vector(ray.direction()),
+            ray.time(),
+        );
+
+        let hit = self.object.hit(&object_ray, t)?;
+
+        Some(
+            HitRecord::new(
+                transform.transform_point(hit.point()),
+                transform.transform_normal(hit.normal()),
+                hit.material(),
+                hit.t(),
+                hit.front_face(),
+                hit.u(),
+                hit.v(),
+            )
+            .with_vertex_color(hit.vertex_color()),
+        )
+    }
+}
+
+// Lights inside an instance are sampled in the space of its object
+impl Sampleable for Instance {
+    fn sample_direction(&self, origin: Vector3, time: f64) -> Option<Vector3> {
+        let transform = self.transform_at(time)?;
+        let origin = transform.inverse().transform_point(origin);
+        let direction = self.object.sample_direction(origin, time)?;
+        Some(transform.transform_vector(direction))
+    }
+
+    fn pdf_value(&self, origin: Vector3, direction: Vector3, time: f64) -> f64 {
+        let Some(transform) = self.transform_at(time) else {
+            return 0.0;
+        };
+        let inverse = transform.inverse();
+        let local_direction = inverse.transform_vector(unit_vector(direction));
+        let pdf = self
+            .object
+            .pdf_value(inverse.transform_point(origin), local_direction, time);
+
+        // Mapping unit directions through the inverse stretches their solid angles by
+        // |det| / length³ of the mapped direction
+        pdf * determinant(linear_part(inverse)).abs() / local_direction.length().powi(3)
+    }
+}
+
+type Matrix3 = [[f64; 3]; 3];
+
+// Affine transform split into a translation, a rotation and a symmetric stretch applied in
+// reverse order, through a polar decomposition as in Shoemake and Duff, "Matrix Animation
+// and Polar Decomposition" (1992). A mirroring is folded into the stretch.
+#[derive(Debug, Clone, Copy)]
+struct Decomposed {
+    translation: Vector3,
+    rotation: Quaternion,
+    stretch: Matrix3,
+}
+
+impl Decomposed {
+    // The matrix must be affine and invertible, as for a Transform
+    fn new(matrix: Matrix4) -> Self {
+        let translation = Vector3::new(matrix.get(0, 3), matrix.get(1, 3), matrix.get(2, 3));
+        let linear = linear_part(matrix);
+
+        // Averaging with the inverse transpose converges to the closest orthonormal matrix
+        let mut rotation = linear;
+        for _ in 0..100 {
+            let Some(inverse) = inverse(rotation) else {
+                break;
+            };
+            let next: Matrix3 = std::array::from_fn(|row| {
+                std::array::from_fn(|column| (rotation[row][column] + inverse[column][row]) / 2.0)
+            });
+            let change = (0..3)
+                .flat_map(|row| (0..3).map(move |column| (row, column)))
+                .map(|(row, column)| (next[row][column] - rotation[row][column]).abs())
+                .fold(0.0, f64::max);
+            rotation = next;
+            if change < 1e-12 {
+                break;
+            }
+        }
+
+        // Quaternions only hold proper rotations, a mirroring goes into the stretch
+        if determinant(rotation) < 0.0 {
+            rotation = rotation.map(|row| row.map(|value| -value));
+        }
+        let stretch = product(transpose(rotation), linear);
+
+        Self {
+            translation,
+            rotation: Quaternion::from_rotation_matrix(rotation),
+            stretch,
+        }
+    }
+
+    // The transform a fraction t of the way to `other`, with its inverse built from the
+    // parts. None when the blended stretch is singular.
+    fn lerp(&self, other: &Decomposed, t: f64) -> Option<Transform> {
+        let translation = self.translation * (1.0 - t) + other.translation * t;
+        let rotation = self.rotation.slerp(&other.rotation, t).to_rotation_matrix();
+        let stretch: Matrix3 = std::array::from_fn(|row| {
+            std::array::from_fn(|column| {
+                self.stretch[row][column] * (1.0 - t) + other.stretch[row][column] * t
+            })
+        });
+
+        let linear = product(rotation, stretch);
+        // The rotation's inverse is its transpose
+        let inverse_linear = product(inverse(stretch)?, transpose(rotation));
+        let inverse_translation = -multiply(inverse_linear, translation);
+
+        Some(Transform {
+            matrix: affine(linear, translation),
+            inverse: affine(inverse_linear, inverse_translation),
+        })
+    }
+}
+
+fn linear_part(matrix: Matrix4) -> Matrix3 {
+    std::array::from_fn(|row| std::array::from_fn(|column| matrix.get(row, column)))
+}
+
+fn affine(linear: Matrix3, translation: Vector3) -> Matrix4 {
+    let [a, b, c] = linear;
+    Matrix4::new([
+        [a[0], a[1], a[2], translation.x()],
+        [b[0], b[1], b[2], translation.y()],
+        [c[0], c[1], c[2], translation.z()],
+        [0.0, 0.0, 0.0, 1.0],
+    ])
+}
+
+fn multiply(m: Matrix3, v: Vector3) -> Vector3 {
+    Vector3::new(
+        m[0][0] * v.x() + m[0][1] * v.y() + m[0][2] * v.z(),
+        m[1][0] * v.x() + m[1][1] * v.y() + m[1][2] * v.z(),
+        m[2][0] * v.x() + m[2][1] * v.y() + m[2][2] * v.z(),
+    )
+}
+
+fn product(a: Matrix3, b: Matrix3) -> Matrix3 {
+    std::array::from_fn(|row| {
+        std::array::from_fn(|column| (0..3).map(|k| a[row][k] * b[k][column]).sum())
+    })
+}
+
+fn transpose(m: Matrix3) -> Matrix3 {
+    std::array::from_fn(|row| std::array::from_fn(|column| m[column][row]))
+}
+
+fn determinant(m: Matrix3) -> f64 {
+    m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+        - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+        + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+}
+
+// Adjugate over the determinant, None for singular matrices
+fn inverse(m: Matrix3) -> Option<Matrix3> {
+    let determinant = determinant(m);
+    if determinant.abs() < 1e-12 {
+        return None;
+    }
+    // Cofactors of the transpose, taken cyclically so the signs come out right
+    Some(std::array::from_fn(|row| {
+        std::array::from_fn(|column| {
+            let (r1, r2) = ((column + 1) % 3, (column + 2) % 3);
+            let (c1, c2) = ((row + 1) % 3, (row + 2) % 3);
+            (m[r1][c1] * m[r2][c2] - m[r1][c2] * m[r2][c1]) / determinant
+        })
+    }))
+}
+
+fn corners(bounding_box: AABB) -> [Vector3; 8] {
+    let (x, y, z) = (
+        bounding_box.axis_interval(0),
+        bounding_box.axis_interval(1),
+        bounding_box.axis_interval(2),
+    );
+    std::array::from_fn(|index| {
+        Vector3::new(
+            if index & 1 == 0 { x.min() } else { x.max() },
+            if index & 2 == 0 { y.min() } else { y.max() },
+            if index & 4 == 0 { z.min() } else { z.max() },
+        )
+    })
+}
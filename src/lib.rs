@@ -1,11 +1,34 @@
 pub mod aabb;
+pub mod bsdf;
 pub mod bvh;
 pub mod camera;
+pub mod environment;
+pub mod error;
+mod gltf_import;
+pub mod instance;
 pub mod material;
+pub mod matrix;
+pub mod medium;
 pub mod mesh;
+pub mod microfacet;
+pub mod mtl;
+mod obj;
+pub mod onb;
+pub mod output;
+pub mod perlin;
+mod ply;
+pub mod presets;
+pub mod principled;
 pub mod quad;
+pub mod quaternion;
 pub mod ray;
+pub mod scene;
+pub mod sky;
 pub mod sphere;
+mod stl;
+pub mod texture;
+pub mod tonemap;
+pub mod triangle;
 pub mod util;
 pub mod vec;
 pub mod world;
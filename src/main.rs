@@ -1,42 +1,178 @@
 // https://raytracing.github.io/books/RayTracingInOneWeekend.html
 
-use std::sync::Arc;
+use std::{
+    path::{Path, PathBuf},
+    process,
+};
 
+use clap::{Parser, ValueEnum};
+#[cfg(feature = "window")]
 use minifb::{Key, Window, WindowOptions};
 use tracer::{
-    camera::Camera,
-    material::{Dielectric, Lambertian, Material, Metal},
-    ray::WorldObject,
-    sphere::Sphere,
-    util::{random_color, random_color_range, random_float, random_unit_float},
+    output, presets,
+    scene::Scene,
+    tonemap::{ToneMapper, ToneMapping},
     vec::Vector3,
-    world::World,
 };
 
+#[derive(Parser)]
+#[command(version, about = "Basic ray tracer")]
+struct Args {
+    /// TOML scene description or glTF file to render
+    scene: Option<PathBuf>,
+
+    /// Built-in scene to render when no scene file is given
+    #[arg(long, value_enum, default_value_t = Preset::Spheres, conflicts_with = "scene")]
+    preset: Preset,
+
+    /// Image width in pixels, the height follows from the aspect ratio unless given
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    width: Option<u32>,
+
+    /// Image height in pixels, the width follows from the aspect ratio unless given
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    height: Option<u32>,
+
+    /// Samples per pixel
+    #[arg(short, long, value_parser = clap::value_parser!(u32).range(1..))]
+    samples: Option<u32>,
+
+    /// Maximum number of bounces per path
+    #[arg(long)]
+    max_depth: Option<u32>,
+
+    /// Write the render to this file (.png, .ppm, .exr or .hdr)
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Number of render threads, defaults to one per core
+    #[arg(short = 'j', long)]
+    threads: Option<usize>,
+
+    /// Don't open a window, only write the output file
+    #[arg(long)]
+    headless: bool,
+
+    /// Tone mapping operator used for displayable output
+    #[arg(long, value_enum, default_value_t = ToneMap::Clamp)]
+    tone_map: ToneMap,
+
+    /// Exposure adjustment in stops
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    exposure: f64,
+
+    /// Luminance mapped to white by the extended Reinhard operator
+    #[arg(long, default_value_t = 4.0)]
+    white_point: f64,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Preset {
+    Spheres,
+    Cornell,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ToneMap {
+    Clamp,
+    Reinhard,
+    ExtendedReinhard,
+    Aces,
+}
+
 fn main() {
-    let width = 800;
-    let from = Vector3::new(13.0, 2.0, 3.0);
-    let to = Vector3::new(0.0, 0.0, 0.0);
-    let vertical_fov = 20.0;
-    let focus_distance = 10.0;
-    let background = Vector3::new(0.7, 0.8, 1.0);
-    let defocus_angle = 0.0;
-    let samples_per_pixel = 250;
-
-    let camera = Camera::new(
-        width,
-        from,
-        to,
-        vertical_fov,
-        focus_distance,
-        defocus_angle,
-        samples_per_pixel,
-        background,
-    );
+    let args = Args::parse();
+
+    if let Some(threads) = args.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .unwrap_or_else(|e| fail(format!("Failed to set up {} threads: {}", threads, e)));
+    }
+
+    let scene = match &args.scene {
+        Some(scene_path) => Scene::from_file(scene_path).unwrap_or_else(|e| fail(e.to_string())),
+        None => match args.preset {
+            Preset::Spheres => presets::random_spheres(),
+            Preset::Cornell => presets::cornell_box(),
+        },
+    };
+    let (mut camera, world) = scene.into_parts();
+
+    match (args.width, args.height) {
+        (Some(width), Some(height)) => camera.set_resolution(width, height),
+        (Some(width), None) => camera.set_width(width),
+        (None, Some(height)) => camera.set_height(height),
+        (None, None) => {}
+    }
+    if let Some(samples) = args.samples {
+        camera.set_samples_per_pixel(samples);
+    }
+    if let Some(max_depth) = args.max_depth {
+        camera.set_max_depth(max_depth);
+    }
+
+    let operator = match args.tone_map {
+        ToneMap::Clamp => ToneMapping::Clamp,
+        ToneMap::Reinhard => ToneMapping::Reinhard,
+        ToneMap::ExtendedReinhard => ToneMapping::ExtendedReinhard {
+            white_point: args.white_point,
+        },
+        ToneMap::Aces => ToneMapping::Aces,
+    };
+    let tone_mapper = ToneMapper::new(operator, args.exposure);
+
+    let headless = args.headless || !cfg!(feature = "window");
+    let output_path = match args.output {
+        Some(path) => Some(path),
+        None if headless => Some(PathBuf::from("render.png")),
+        None => None,
+    };
+
+    let width = camera.width();
     let height = camera.height();
 
-    let mut frame_buffer = vec![0; width as usize * height as usize];
+    let mut hdr_buffer = vec![Vector3::zero(); width as usize * height as usize];
+    camera.render_hdr(&world, &mut hdr_buffer);
+
+    let frame_buffer: Vec<u32> = hdr_buffer
+        .iter()
+        .map(|color| tone_mapper.to_pixel(*color))
+        .collect();
+
+    if let Some(path) = &output_path {
+        // .exr and .hdr outputs keep the full linear radiance of the render
+        if output::is_hdr_path(path) {
+            save_hdr(path, width, height, &hdr_buffer);
+        } else {
+            save(path, width, height, &frame_buffer);
+        }
+    }
+
+    if !headless {
+        present(width, height, &frame_buffer);
+    }
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+fn save_hdr(path: &Path, width: u32, height: u32, frame_buffer: &[Vector3]) {
+    output::save_hdr(path, width, height, frame_buffer)
+        .unwrap_or_else(|e| fail(format!("Failed to write {}: {}", path.display(), e)));
+    println!("Wrote {}", path.display());
+}
 
+fn save(path: &Path, width: u32, height: u32, frame_buffer: &[u32]) {
+    output::save(path, width, height, frame_buffer)
+        .unwrap_or_else(|e| fail(format!("Failed to write {}: {}", path.display(), e)));
+    println!("Wrote {}", path.display());
+}
+
+#[cfg(feature = "window")]
+fn present(width: u32, height: u32, frame_buffer: &[u32]) {
     let mut window = Window::new(
         "tracer - ESC to exit",
         width as usize,
@@ -47,69 +183,13 @@ fn main() {
         panic!("{}", e);
     });
 
-    let mut objects: Vec<Arc<WorldObject>> = vec![];
-
-    let material_ground = Material::Lambertian(Lambertian::new(Vector3::new(0.5, 0.5, 0.5)));
-    objects.push(WorldObject::Sphere(Sphere::new(
-        Vector3::new(0.0, -1000.0, 0.0),
-        1000.0,
-        material_ground,
-    )).into());
-
-    let material_glass = Material::Dielectric(Dielectric::new(1.50));
-    objects.push(WorldObject::Sphere(Sphere::new(
-        Vector3::new(0.0, 1.0, 0.0),
-        1.0,
-        material_glass,
-    )).into());
-
-    let material_diffuse = Material::Lambertian(Lambertian::new(Vector3::new(0.4, 0.2, 0.1)));
-    objects.push(WorldObject::Sphere(Sphere::new(
-        Vector3::new(-4.0, 1.0, 0.0),
-        1.0,
-        material_diffuse,
-    )).into());
-
-    let material_metal = Material::Metal(Metal::new(Vector3::new(0.7, 0.6, 0.5), 0.0));
-    objects.push(WorldObject::Sphere(Sphere::new(
-        Vector3::new(4.0, 1.0, 0.0),
-        1.0,
-        material_metal,
-    )).into());
-
-    for x in -11..11 {
-        for z in -11..11 {
-            let random_material = random_unit_float();
-            let center = Vector3::new(
-                x as f64 + 0.9 * random_unit_float(),
-                0.2,
-                z as f64 + 0.9 * random_unit_float(),
-            );
-
-            if (center - Vector3::new(4.0, 0.2, 0.0)).length() > 0.9 {
-                let material = match random_material {
-                    _ if (0.0..=0.8).contains(&random_material) => {
-                        Material::Lambertian(Lambertian::new(random_color() * random_color()))
-                    }
-                    _ if (0.8..=0.95).contains(&random_material) => Material::Metal(Metal::new(
-                        random_color_range(0.5, 1.0),
-                        random_float(0.0, 0.5),
-                    )),
-                    _ => Material::Dielectric(Dielectric::new(1.50)),
-                };
-
-                objects.push(WorldObject::Sphere(Sphere::new(center, 0.2, material)).into());
-            }
-        }
-    }
-
-    let world = World::new(objects);
-
-    camera.render(&world, &mut frame_buffer);
-
     while window.is_open() && !window.is_key_down(Key::Escape) {
         window
-            .update_with_buffer(&frame_buffer, width as usize, height as usize)
+            .update_with_buffer(frame_buffer, width as usize, height as usize)
             .unwrap();
     }
 }
+
+// Headless builds never present, renders always go to a file
+#[cfg(not(feature = "window"))]
+fn present(_width: u32, _height: u32, _frame_buffer: &[u32]) {}
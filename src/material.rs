@@ -1,89 +1,168 @@
+use std::f64::consts::PI;
+
 use crate::{
-    ray::{HitRecord, Ray},
+    bsdf::{Bsdf, BsdfFlags, BsdfSample, Bxdf, Scatterable},
+    microfacet::{
+        fresnel_conductor, fresnel_dielectric, microfacet_normal, reflect, refract, TrowbridgeReitz,
+    },
+    principled::Principled,
+    ray::HitRecord,
+    texture::Texture,
     util::random_unit_float,
     vec::{dot_product, unit_vector, Vector3},
 };
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum Material {
     Lambertian(Lambertian),
     Metal(Metal),
     Dielectric(Dielectric),
     Light(Light),
+    Isotropic(Isotropic),
+    Conductor(Conductor),
+    RoughDielectric(RoughDielectric),
+    // Boxed, its textures would make every material several times larger
+    Principled(Box<Principled>),
 }
 
 impl Material {
-    pub fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord) -> Option<(Ray, Vector3)> {
-        match self {
-            Material::Lambertian(lambertian) => lambertian.scatter(ray_in, hit_record),
-            Material::Metal(metal) => metal.scatter(ray_in, hit_record),
-            Material::Dielectric(dielectric) => dielectric.scatter(ray_in, hit_record),
-            _ => None,
-        }
+    // Scattering at the hit, None for lights, which only emit
+    pub fn bsdf(&self, hit_record: &HitRecord) -> Option<Bsdf> {
+        let bxdf = match self {
+            Material::Lambertian(lambertian) => lambertian.bxdf(hit_record),
+            Material::Metal(metal) => metal.bxdf(hit_record),
+            Material::Dielectric(dielectric) => dielectric.bxdf(hit_record),
+            Material::Isotropic(isotropic) => isotropic.bxdf(hit_record),
+            Material::Conductor(conductor) => Bxdf::Conductor(*conductor),
+            Material::RoughDielectric(dielectric) => dielectric.bxdf(hit_record),
+            Material::Principled(principled) => principled.bxdf(hit_record),
+            Material::Light(_) => return None,
+        };
+
+        Some(Bsdf::new(hit_record.normal(), bxdf))
     }
 
     pub fn emitted(&self, point: Vector3) -> Vector3 {
         match self {
             Material::Light(light) => light.emitted(point),
+            Material::Isotropic(isotropic) => isotropic.emitted(),
             _ => Vector3::zero(),
         }
     }
-}
 
-pub trait Scatterable {
-    fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord) -> Option<(Ray, Vector3)>;
+    pub fn is_emissive(&self) -> bool {
+        matches!(self, Material::Light(_))
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct Lambertian {
-    albedo: Vector3,
+    albedo: Texture,
 }
 
 impl Lambertian {
     pub fn new(albedo: Vector3) -> Self {
+        Self::from_texture(albedo.into())
+    }
+
+    pub fn from_texture(albedo: Texture) -> Self {
         Self { albedo }
     }
+
+    pub fn bxdf(&self, hit_record: &HitRecord) -> Bxdf {
+        Bxdf::Diffuse(DiffuseBxdf {
+            albedo: self.albedo.value_at_hit(hit_record),
+        })
+    }
 }
 
-impl Scatterable for Lambertian {
-    fn scatter(&self, _ray_in: &Ray, hit_record: &HitRecord) -> Option<(Ray, Vector3)> {
-        let scatter_direction = hit_record.normal() + Vector3::random_unit_vector();
+#[derive(Debug, Clone, Copy)]
+pub struct DiffuseBxdf {
+    albedo: Vector3,
+}
 
-        let direction = if scatter_direction.near_zero() {
-            hit_record.normal()
+impl Scatterable for DiffuseBxdf {
+    // The normal plus a random unit vector is cosine weighted
+    fn sample(&self, wo: Vector3) -> Option<BsdfSample> {
+        let normal = Vector3::new(0.0, 0.0, 1.0);
+        let direction = normal + Vector3::random_unit_vector();
+
+        let wi = if direction.near_zero() {
+            normal
         } else {
-            scatter_direction
+            unit_vector(direction)
         };
 
-        let ray = Ray::new(hit_record.point(), direction);
+        Some(BsdfSample::new(
+            wi,
+            self.eval(wo, wi),
+            self.pdf(wo, wi),
+            self.flags(),
+        ))
+    }
+
+    fn eval(&self, wo: Vector3, wi: Vector3) -> Vector3 {
+        self.albedo * self.pdf(wo, wi)
+    }
+
+    fn pdf(&self, _wo: Vector3, wi: Vector3) -> f64 {
+        (wi.z() / PI).max(0.0)
+    }
 
-        Some((ray, self.albedo))
+    fn flags(&self) -> BsdfFlags {
+        BsdfFlags::DIFFUSE | BsdfFlags::REFLECTION
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct Metal {
-    albedo: Vector3,
+    albedo: Texture,
     fuzz: f64,
 }
 
 impl Metal {
     pub fn new(albedo: Vector3, fuzz: f64) -> Self {
+        Self::from_texture(albedo.into(), fuzz)
+    }
+
+    pub fn from_texture(albedo: Texture, fuzz: f64) -> Self {
         Self { albedo, fuzz }
     }
+
+    pub fn bxdf(&self, hit_record: &HitRecord) -> Bxdf {
+        Bxdf::FuzzyReflection(FuzzyReflectionBxdf {
+            albedo: self.albedo.value_at_hit(hit_record),
+            fuzz: self.fuzz,
+        })
+    }
+}
+
+// Mirror reflection pushed in a random direction. The fuzz has no density, so it counts
+// as specular.
+#[derive(Debug, Clone, Copy)]
+pub struct FuzzyReflectionBxdf {
+    albedo: Vector3,
+    fuzz: f64,
 }
 
-impl Scatterable for Metal {
-    fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord) -> Option<(Ray, Vector3)> {
-        let reflected = Vector3::reflect(ray_in.direction(), hit_record.normal());
+impl Scatterable for FuzzyReflectionBxdf {
+    fn sample(&self, wo: Vector3) -> Option<BsdfSample> {
+        let reflected = Vector3::new(-wo.x(), -wo.y(), wo.z());
+        let wi = unit_vector(reflected + (Vector3::random_unit_vector() * self.fuzz));
 
-        let direction = unit_vector(reflected) + (Vector3::random_unit_vector() * self.fuzz);
+        (wi.z() > 0.0).then(|| BsdfSample::new(wi, self.albedo, 1.0, self.flags()))
+    }
 
-        if dot_product(direction, hit_record.normal()) > 0.0 {
-            Some((Ray::new(hit_record.point(), direction), self.albedo))
-        } else {
-            None
-        }
+    fn eval(&self, _wo: Vector3, _wi: Vector3) -> Vector3 {
+        Vector3::zero()
+    }
+
+    fn pdf(&self, _wo: Vector3, _wi: Vector3) -> f64 {
+        0.0
+    }
+
+    fn flags(&self) -> BsdfFlags {
+        BsdfFlags::SPECULAR | BsdfFlags::REFLECTION
     }
 }
 
@@ -97,40 +176,294 @@ impl Dielectric {
         Self { refraction_index }
     }
 
-    fn reflectance(cosine: f64, refraction_index: f64) -> f64 {
-        // Schlick Approximation of reflectance
-        let r0 = ((1.0 - refraction_index) / (1.0 + refraction_index)).powi(2);
-        r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
+    pub fn bxdf(&self, hit_record: &HitRecord) -> Bxdf {
+        let eta = if hit_record.front_face() {
+            self.refraction_index
+        } else {
+            1.0 / self.refraction_index
+        };
+
+        Bxdf::SpecularDielectric(SpecularDielectricBxdf { eta })
     }
 }
 
-impl Scatterable for Dielectric {
-    fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord) -> Option<(Ray, Vector3)> {
-        let normal = hit_record.normal();
-        let refraction_index = if hit_record.front_face() {
-            1.0 / self.refraction_index
+// Smooth glass, the same as a RoughDielectric with a roughness of 0
+#[derive(Debug, Clone, Copy)]
+pub struct SpecularDielectricBxdf {
+    // Ratio of the refraction index behind the surface to the one on the path's side
+    eta: f64,
+}
+
+impl Scatterable for SpecularDielectricBxdf {
+    // Reflects or refracts in proportion to the Fresnel reflectance, which cancels with it
+    fn sample(&self, wo: Vector3) -> Option<BsdfSample> {
+        if wo.z() <= 0.0 {
+            return None;
+        }
+
+        let normal = Vector3::new(0.0, 0.0, 1.0);
+        let fresnel = fresnel_dielectric(wo.z(), self.eta);
+        if random_unit_float() < fresnel {
+            return Some(BsdfSample::new(
+                reflect(wo, normal),
+                grey(fresnel),
+                fresnel,
+                BsdfFlags::SPECULAR | BsdfFlags::REFLECTION,
+            ));
+        }
+
+        // The reflectance is 1 on total internal reflection, so this always refracts
+        let wi = refract(wo, normal, self.eta)?;
+        // Radiance gets compressed into the smaller solid angle of the denser side
+        Some(BsdfSample::new(
+            wi,
+            grey((1.0 - fresnel) / (self.eta * self.eta)),
+            1.0 - fresnel,
+            BsdfFlags::SPECULAR | BsdfFlags::TRANSMISSION,
+        ))
+    }
+
+    fn eval(&self, _wo: Vector3, _wi: Vector3) -> Vector3 {
+        Vector3::zero()
+    }
+
+    fn pdf(&self, _wo: Vector3, _wi: Vector3) -> f64 {
+        0.0
+    }
+
+    fn flags(&self) -> BsdfFlags {
+        BsdfFlags::SPECULAR | BsdfFlags::REFLECTION | BsdfFlags::TRANSMISSION
+    }
+}
+
+// Metal with a rough microfacet surface, reflecting as much as the Fresnel equations give
+// for its complex refraction index. Unlike Metal's fuzz this conserves energy and has a
+// density, so it works with light sampling. It has no textures and is its own BxDF.
+#[derive(Debug, Clone, Copy)]
+pub struct Conductor {
+    // Real and imaginary parts of the refraction index at red, green and blue wavelengths
+    eta: Vector3,
+    k: Vector3,
+    distribution: TrowbridgeReitz,
+}
+
+impl Conductor {
+    pub fn new(eta: Vector3, k: Vector3, roughness: f64) -> Self {
+        Self {
+            eta,
+            k,
+            distribution: TrowbridgeReitz::new(roughness),
+        }
+    }
+
+    pub fn gold(roughness: f64) -> Self {
+        Self::new(
+            Vector3::new(0.143, 0.374, 1.442),
+            Vector3::new(3.983, 2.385, 1.603),
+            roughness,
+        )
+    }
+
+    pub fn copper(roughness: f64) -> Self {
+        Self::new(
+            Vector3::new(0.200, 0.924, 1.102),
+            Vector3::new(3.912, 2.452, 2.142),
+            roughness,
+        )
+    }
+
+    pub fn aluminium(roughness: f64) -> Self {
+        Self::new(
+            Vector3::new(1.657, 0.880, 0.521),
+            Vector3::new(9.224, 6.270, 4.837),
+            roughness,
+        )
+    }
+
+    pub fn is_smooth(&self) -> bool {
+        self.distribution.is_smooth()
+    }
+}
+
+impl Scatterable for Conductor {
+    fn sample(&self, wo: Vector3) -> Option<BsdfSample> {
+        // Interpolated normals can face away from the path
+        if wo.z() <= 0.0 {
+            return None;
+        }
+
+        if self.is_smooth() {
+            let wi = Vector3::new(-wo.x(), -wo.y(), wo.z());
+            let fresnel = fresnel_conductor(wo.z(), self.eta, self.k);
+            return Some(BsdfSample::new(wi, fresnel, 1.0, self.flags()));
+        }
+
+        let wi = reflect(wo, self.distribution.sample_visible_normal(wo));
+        let pdf = self.pdf(wo, wi);
+        (pdf > 0.0).then(|| BsdfSample::new(wi, self.eval(wo, wi), pdf, self.flags()))
+    }
+
+    fn eval(&self, wo: Vector3, wi: Vector3) -> Vector3 {
+        if self.is_smooth() || wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return Vector3::zero();
+        }
+
+        let wm = unit_vector(wo + wi);
+        let fresnel = fresnel_conductor(dot_product(wo, wm), self.eta, self.k);
+        fresnel * (self.distribution.d(wm) * self.distribution.g(wo, wi) / (4.0 * wo.z()))
+    }
+
+    fn pdf(&self, wo: Vector3, wi: Vector3) -> f64 {
+        if self.is_smooth() || wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return 0.0;
+        }
+
+        let wm = unit_vector(wo + wi);
+        self.distribution.visible_pdf(wo, wm) / (4.0 * dot_product(wo, wm))
+    }
+
+    fn flags(&self) -> BsdfFlags {
+        if self.is_smooth() {
+            BsdfFlags::SPECULAR | BsdfFlags::REFLECTION
         } else {
+            BsdfFlags::GLOSSY | BsdfFlags::REFLECTION
+        }
+    }
+}
+
+// Glass with a rough microfacet surface, which blurs both its reflections and what is
+// seen through it
+#[derive(Debug, Clone, Copy)]
+pub struct RoughDielectric {
+    refraction_index: f64,
+    distribution: TrowbridgeReitz,
+}
+
+impl RoughDielectric {
+    pub fn new(refraction_index: f64, roughness: f64) -> Self {
+        Self {
+            refraction_index,
+            distribution: TrowbridgeReitz::new(roughness),
+        }
+    }
+
+    pub fn bxdf(&self, hit_record: &HitRecord) -> Bxdf {
+        let eta = if hit_record.front_face() {
             self.refraction_index
+        } else {
+            1.0 / self.refraction_index
         };
 
-        let unit_direction = unit_vector(ray_in.direction());
-        let cos_theta = dot_product(-unit_direction, normal).min(1.0);
-        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        Bxdf::RoughDielectric(RoughDielectricBxdf {
+            eta,
+            distribution: self.distribution,
+        })
+    }
+}
 
-        // Determine whether there is a solution using Snells law.
-        // If not, cannot refract and must reflect.
-        let direction = if refraction_index * sin_theta > 1.0
-            || Dielectric::reflectance(cos_theta, refraction_index) > random_unit_float()
-        {
-            Vector3::reflect(unit_direction, normal)
+#[derive(Debug, Clone, Copy)]
+pub struct RoughDielectricBxdf {
+    // Ratio of the refraction index behind the surface to the one on the path's side
+    eta: f64,
+    distribution: TrowbridgeReitz,
+}
+
+impl Scatterable for RoughDielectricBxdf {
+    // Reflects or refracts in proportion to the Fresnel term of the sampled microfacet
+    fn sample(&self, wo: Vector3) -> Option<BsdfSample> {
+        if wo.z() <= 0.0 {
+            return None;
+        }
+
+        let smooth = self.distribution.is_smooth();
+        let wm = if smooth {
+            Vector3::new(0.0, 0.0, 1.0)
         } else {
-            Vector3::refract(unit_direction, normal, refraction_index)
+            self.distribution.sample_visible_normal(wo)
         };
 
-        Some((
-            Ray::new(hit_record.point(), direction),
-            Vector3::new(1.0, 1.0, 1.0),
-        ))
+        let fresnel = fresnel_dielectric(dot_product(wo, wm), self.eta);
+        let reflected = random_unit_float() < fresnel;
+        let wi = if reflected {
+            reflect(wo, wm)
+        } else {
+            refract(wo, wm, self.eta)?
+        };
+        if (wi.z() > 0.0) != reflected || wi.z() == 0.0 {
+            return None;
+        }
+
+        if smooth {
+            let (f, pdf, flags) = if reflected {
+                (fresnel, fresnel, BsdfFlags::REFLECTION)
+            } else {
+                // Radiance gets compressed into the smaller solid angle of the denser side
+                let f = (1.0 - fresnel) / (self.eta * self.eta);
+                (f, 1.0 - fresnel, BsdfFlags::TRANSMISSION)
+            };
+            return Some(BsdfSample::new(
+                wi,
+                grey(f),
+                pdf,
+                BsdfFlags::SPECULAR | flags,
+            ));
+        }
+
+        let pdf = self.pdf(wo, wi);
+        let flags = if reflected {
+            BsdfFlags::GLOSSY | BsdfFlags::REFLECTION
+        } else {
+            BsdfFlags::GLOSSY | BsdfFlags::TRANSMISSION
+        };
+        (pdf > 0.0).then(|| BsdfSample::new(wi, self.eval(wo, wi), pdf, flags))
+    }
+
+    fn eval(&self, wo: Vector3, wi: Vector3) -> Vector3 {
+        if self.distribution.is_smooth() {
+            return Vector3::zero();
+        }
+        let Some(wm) = microfacet_normal(wo, wi, self.eta) else {
+            return Vector3::zero();
+        };
+
+        let fresnel = fresnel_dielectric(dot_product(wo, wm), self.eta);
+        let microfacets = self.distribution.d(wm) * self.distribution.g(wo, wi);
+        let value = if wi.z() > 0.0 {
+            microfacets * fresnel / (4.0 * wo.z())
+        } else {
+            let denominator = (dot_product(wi, wm) + dot_product(wo, wm) / self.eta).powi(2);
+            microfacets * (1.0 - fresnel) * (dot_product(wi, wm) * dot_product(wo, wm)).abs()
+                / (wo.z() * denominator * self.eta * self.eta)
+        };
+
+        grey(value)
+    }
+
+    fn pdf(&self, wo: Vector3, wi: Vector3) -> f64 {
+        if self.distribution.is_smooth() {
+            return 0.0;
+        }
+        let Some(wm) = microfacet_normal(wo, wi, self.eta) else {
+            return 0.0;
+        };
+
+        let fresnel = fresnel_dielectric(dot_product(wo, wm), self.eta);
+        let visible = self.distribution.visible_pdf(wo, wm);
+        if wi.z() > 0.0 {
+            visible / (4.0 * dot_product(wo, wm)) * fresnel
+        } else {
+            let denominator = (dot_product(wi, wm) + dot_product(wo, wm) / self.eta).powi(2);
+            visible * dot_product(wi, wm).abs() / denominator * (1.0 - fresnel)
+        }
+    }
+
+    fn flags(&self) -> BsdfFlags {
+        let scattering = BsdfFlags::REFLECTION | BsdfFlags::TRANSMISSION;
+        if self.distribution.is_smooth() {
+            BsdfFlags::SPECULAR | scattering
+        } else {
+            BsdfFlags::GLOSSY | scattering
+        }
     }
 }
 
@@ -150,3 +483,74 @@ impl Light {
         self.color
     }
 }
+
+// Phase function of participating media, scatters equally into all directions. The
+// albedo is the fraction of collisions that scatter rather than absorb.
+#[derive(Debug, Clone)]
+pub struct Isotropic {
+    albedo: Texture,
+    // Added at every collision, so denser parts of a medium glow brighter
+    emission: Vector3,
+}
+
+impl Isotropic {
+    pub fn new(albedo: Vector3) -> Self {
+        Self::from_texture(albedo.into())
+    }
+
+    pub fn from_texture(albedo: Texture) -> Self {
+        Self {
+            albedo,
+            emission: Vector3::zero(),
+        }
+    }
+
+    pub fn emission(mut self, emission: Vector3) -> Self {
+        self.emission = emission;
+        self
+    }
+
+    pub fn emitted(&self) -> Vector3 {
+        self.emission
+    }
+
+    pub fn bxdf(&self, hit_record: &HitRecord) -> Bxdf {
+        Bxdf::Isotropic(IsotropicBxdf {
+            albedo: self.albedo.value_at_hit(hit_record),
+        })
+    }
+}
+
+// There is no surface, so no cosine term either and the frame doesn't matter
+#[derive(Debug, Clone, Copy)]
+pub struct IsotropicBxdf {
+    albedo: Vector3,
+}
+
+impl Scatterable for IsotropicBxdf {
+    fn sample(&self, wo: Vector3) -> Option<BsdfSample> {
+        let wi = Vector3::random_unit_vector();
+        Some(BsdfSample::new(
+            wi,
+            self.eval(wo, wi),
+            self.pdf(wo, wi),
+            self.flags(),
+        ))
+    }
+
+    fn eval(&self, wo: Vector3, wi: Vector3) -> Vector3 {
+        self.albedo * self.pdf(wo, wi)
+    }
+
+    fn pdf(&self, _wo: Vector3, _wi: Vector3) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+
+    fn flags(&self) -> BsdfFlags {
+        BsdfFlags::DIFFUSE | BsdfFlags::REFLECTION | BsdfFlags::TRANSMISSION
+    }
+}
+
+pub(crate) fn grey(value: f64) -> Vector3 {
+    Vector3::new(value, value, value)
+}
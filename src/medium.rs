@@ -0,0 +1,315 @@
+use std::{fs, path::Path, sync::Arc};
+
+use crate::{
+    aabb::AABB,
+    error::TracerError,
+    material::{Isotropic, Material},
+    perlin::Perlin,
+    ray::{HitRecord, Hittable, Interval, Ray, WorldObject},
+    util::random_unit_float,
+    vec::Vector3,
+};
+
+// Below this transmittance, ratio tracking gives up on half of its paths
+const ROULETTE_TRANSMITTANCE: f64 = 0.1;
+
+// Volume of constant density inside a boundary, such as smoke or fog. Rays scatter
+// inside it after an exponentially distributed distance, which is only sampled between
+// the first two boundary crossings, so the boundary should be convex.
+pub struct ConstantMedium {
+    boundary: Arc<WorldObject>,
+    negative_inverse_density: f64,
+    phase_function: Material,
+}
+
+impl ConstantMedium {
+    pub fn new(boundary: Arc<WorldObject>, density: f64, phase_function: Isotropic) -> Self {
+        // A non-positive density would sample scattering distances behind the ray
+        debug_assert!(density > 0.0, "density must be positive, got {}", density);
+        Self {
+            boundary,
+            negative_inverse_density: -1.0 / density,
+            phase_function: Material::Isotropic(phase_function),
+        }
+    }
+}
+
+impl Hittable for ConstantMedium {
+    fn bounding_box(&self) -> AABB {
+        self.boundary.bounding_box()
+    }
+
+    fn hit(&self, ray: &Ray, t: &Interval) -> Option<HitRecord<'_>> {
+        // The boundary is entered behind the origin when the ray starts inside it
+        let everywhere = Interval::new(f64::NEG_INFINITY, f64::INFINITY);
+        let entry = self.boundary.hit(ray, &everywhere)?;
+        let exit = self
+            .boundary
+            .hit(ray, &Interval::new(entry.t() + 0.0001, f64::INFINITY))?;
+
+        let start = entry.t().max(t.min());
+        let end = exit.t().min(t.max());
+        if start >= end {
+            return None;
+        }
+
+        let ray_length = ray.direction_length_squared().sqrt();
+        let distance_inside = (end - start) * ray_length;
+        let hit_distance = self.negative_inverse_density * random_unit_float().ln();
+        if hit_distance > distance_inside {
+            return None;
+        }
+
+        let root = start + hit_distance / ray_length;
+        // The phase function doesn't depend on the normal, so any will do
+        Some(HitRecord::new(
+            ray.at(root),
+            Vector3::new(1.0, 0.0, 0.0),
+            &self.phase_function,
+            root,
+            true,
+            0.0,
+            0.0,
+        ))
+    }
+}
+
+// Volume whose density varies inside its bounding box, such as a cloud or an explosion.
+// Collisions are found with delta tracking against the peak density, and shadow rays
+// estimate the transmittance with ratio tracking.
+pub struct HeterogeneousMedium {
+    bounding_box: AABB,
+    field: DensityField,
+    // Density where the field is 1, which bounds it everywhere
+    density: f64,
+    phase_function: Material,
+}
+
+impl HeterogeneousMedium {
+    pub fn new(
+        min: Vector3,
+        max: Vector3,
+        field: DensityField,
+        density: f64,
+        phase_function: Isotropic,
+    ) -> Self {
+        // Delta tracking never finishes stepping through a medium without a positive density
+        debug_assert!(density > 0.0, "density must be positive, got {}", density);
+        Self {
+            bounding_box: AABB::from_points(min, max),
+            field,
+            density,
+            phase_function: Material::Isotropic(phase_function),
+        }
+    }
+
+    // Fraction of light that passes through the volume along the ray within `t`
+    pub fn transmittance(&self, ray: &Ray, t: &Interval) -> f64 {
+        let Some(inside) = self.bounding_box.hit(ray, t) else {
+            return 1.0;
+        };
+
+        let ray_length = ray.direction_length_squared().sqrt();
+        let mut transmittance = 1.0;
+        let mut root = inside.min();
+        loop {
+            root += self.step(ray_length);
+            if root >= inside.max() {
+                return transmittance;
+            }
+
+            transmittance *= 1.0 - self.density_at(ray.at(root)) / self.density;
+            if transmittance < ROULETTE_TRANSMITTANCE {
+                if random_unit_float() < 0.5 {
+                    return 0.0;
+                }
+                transmittance *= 2.0;
+            }
+        }
+    }
+
+    // Distance along the ray to the next tentative collision
+    fn step(&self, ray_length: f64) -> f64 {
+        -(1.0 - random_unit_float()).ln() / (self.density * ray_length)
+    }
+
+    fn density_at(&self, point: Vector3) -> f64 {
+        let [x, y, z] = [0, 1, 2].map(|axis| {
+            let interval = self.bounding_box.axis_interval(axis);
+            (point.axis(axis) - interval.min()) / interval.size()
+        });
+        self.density * self.field.value(Vector3::new(x, y, z))
+    }
+}
+
+impl Hittable for HeterogeneousMedium {
+    fn bounding_box(&self) -> AABB {
+        self.bounding_box
+    }
+
+    fn hit(&self, ray: &Ray, t: &Interval) -> Option<HitRecord<'_>> {
+        let inside = self.bounding_box.hit(ray, t)?;
+
+        let ray_length = ray.direction_length_squared().sqrt();
+        let mut root = inside.min();
+        loop {
+            root += self.step(ray_length);
+            if root >= inside.max() {
+                return None;
+            }
+
+            // Tentative collisions in thinner parts are null collisions the ray passes
+            let point = ray.at(root);
+            if self.density_at(point) > self.density * random_unit_float() {
+                return Some(HitRecord::new(
+                    point,
+                    Vector3::new(1.0, 0.0, 0.0),
+                    &self.phase_function,
+                    root,
+                    true,
+                    0.0,
+                    0.0,
+                ));
+            }
+        }
+    }
+}
+
+// Density from 0 to 1, looked up with coordinates from 0 to 1 across the volume's bounds
+pub enum DensityField {
+    Grid(VoxelGrid),
+    Noise(NoiseField),
+}
+
+impl DensityField {
+    pub fn value(&self, point: Vector3) -> f64 {
+        match self {
+            DensityField::Grid(grid) => grid.value(point),
+            DensityField::Noise(noise) => noise.value(point),
+        }
+    }
+}
+
+pub struct VoxelGrid {
+    resolution: [usize; 3],
+    // Ordered with x varying fastest, then y, then z
+    values: Vec<f32>,
+}
+
+impl VoxelGrid {
+    // Values are scaled so that the densest voxel is 1, negative ones are clamped to 0
+    pub fn new(resolution: [usize; 3], values: Vec<f32>) -> Self {
+        assert_eq!(
+            values.len(),
+            resolution.iter().product::<usize>(),
+            "voxel count does not match grid resolution"
+        );
+        assert!(
+            resolution.iter().all(|&size| size > 0),
+            "grid resolution must not be zero"
+        );
+
+        let maximum = values
+            .iter()
+            .fold(0.0_f32, |maximum, &value| maximum.max(value));
+        let scale = if maximum > 0.0 { 1.0 / maximum } else { 0.0 };
+        let values = values
+            .into_iter()
+            .map(|value| (value * scale).max(0.0))
+            .collect();
+
+        Self { resolution, values }
+    }
+
+    // Reads a raw file without a header, holding either one byte or one little-endian
+    // f32 per voxel, which is told apart by the file size
+    pub fn from_file(path: &Path, resolution: [usize; 3]) -> Result<Self, TracerError> {
+        if resolution.contains(&0) {
+            return Err(TracerError::invalid_data(
+                path,
+                "grid resolution must not be zero",
+            ));
+        }
+
+        let bytes = fs::read(path).map_err(|e| TracerError::io(path, e))?;
+        let count: usize = resolution.iter().product();
+        let values = if bytes.len() == count {
+            bytes.iter().map(|&value| value as f32).collect()
+        } else if bytes.len() == count * 4 {
+            bytes
+                .chunks_exact(4)
+                .map(|value| f32::from_le_bytes(value.try_into().unwrap()))
+                .collect()
+        } else {
+            return Err(TracerError::invalid_data(
+                path,
+                format!(
+                    "expected {} voxels of 1 or 4 bytes, but the file has {} bytes",
+                    count,
+                    bytes.len()
+                ),
+            ));
+        };
+
+        Ok(Self::new(resolution, values))
+    }
+
+    // Trilinearly interpolates between the voxel centers
+    fn value(&self, point: Vector3) -> f64 {
+        let mut base = [0; 3];
+        let mut fraction = [0.0; 3];
+        for axis in 0..3 {
+            let size = self.resolution[axis];
+            let coordinate =
+                (point.axis(axis as i32) * size as f64 - 0.5).clamp(0.0, (size - 1) as f64);
+            base[axis] = coordinate.floor() as usize;
+            fraction[axis] = coordinate - base[axis] as f64;
+        }
+
+        let [width, height, _] = self.resolution;
+        let mut value = 0.0;
+        for corner in 0..8 {
+            let mut weight = 1.0;
+            let [x, y, z] = [0, 1, 2].map(|axis| {
+                let offset = (corner >> axis) & 1;
+                weight *= if offset == 1 {
+                    fraction[axis]
+                } else {
+                    1.0 - fraction[axis]
+                };
+                (base[axis] + offset).min(self.resolution[axis] - 1)
+            });
+            value += weight * self.values[x + width * (y + height * z)] as f64;
+        }
+
+        value
+    }
+}
+
+// Turbulence inside the ellipsoid that fits the volume's bounds, fading out towards its
+// surface so the volume looks like a puffy cloud
+pub struct NoiseField {
+    perlin: Perlin,
+    // Roughly how many features fit across the volume
+    scale: f64,
+}
+
+impl NoiseField {
+    pub fn new(scale: f64) -> Self {
+        Self {
+            perlin: Perlin::new(),
+            scale,
+        }
+    }
+
+    fn value(&self, point: Vector3) -> f64 {
+        let offset = point * 2.0 - Vector3::new(1.0, 1.0, 1.0);
+        let falloff = 1.0 - offset.length_squared();
+        if falloff <= 0.0 {
+            return 0.0;
+        }
+
+        let turbulence = self.perlin.turbulence(point * self.scale, 7);
+        (2.0 * (falloff + turbulence) - 1.5).clamp(0.0, 1.0)
+    }
+}
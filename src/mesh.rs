@@ -1,70 +1,524 @@
-use std::{fs::File, io::BufReader, sync::Arc};
-
-use obj::{load_obj, Obj, Position};
+use std::{collections::HashMap, path::Path};
 
 use crate::{
     aabb::AABB,
-    bvh::BVHNode,
-    material::Material,
+    bvh::{BVHStats, SplitMethod, BVH},
+    error::TracerError,
+    material::{Lambertian, Material},
+    mtl::load_mtl,
+    obj::load_obj,
+    ply::load_ply,
     quad::Quad,
-    ray::{HitRecord, Hittable, Interval, Ray, WorldObject},
-    vec::Vector3,
+    ray::{HitRecord, Hittable, Interval, Ray},
+    stl::{load_stl, STL_CREASE_ANGLE},
+    triangle::WatertightRay,
+    vec::{cross_product, dot_product, unit_vector, Vector3},
 };
 
+// Corner of a face, indexing into the vertex attribute lists of a MeshModel
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Corner {
+    pub(crate) position: usize,
+    pub(crate) texture: Option<usize>,
+    pub(crate) normal: Option<usize>,
+    pub(crate) color: Option<usize>,
+}
+
+// Triangle of a polygon, with the line it was read from for error reporting.
+// Binary files have no line numbers.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Face {
+    pub(crate) corners: [Corner; 3],
+    // Index into `MeshModel::material_names`, None before the first `usemtl`
+    pub(crate) material: Option<usize>,
+    pub(crate) line: Option<usize>,
+}
+
+// Triangles and vertex attributes as read by the file loaders, before vertices are merged
+#[derive(Debug, Default)]
+pub(crate) struct MeshModel {
+    pub(crate) positions: Vec<Vector3>,
+    pub(crate) texture_coordinates: Vec<[f64; 2]>,
+    pub(crate) normals: Vec<Vector3>,
+    // Linear RGB
+    pub(crate) colors: Vec<Vector3>,
+    pub(crate) faces: Vec<Face>,
+    pub(crate) material_libraries: Vec<String>,
+    pub(crate) material_names: Vec<String>,
+}
+
+// Position and texture coordinate and color indices plus the normal's bits
+type VertexKey = (usize, Option<usize>, Option<usize>, [u64; 3]);
+
+// Settings for loading a mesh from disk
+#[derive(Debug, Clone)]
+pub struct MeshOptions {
+    crease_angle: Option<f64>,
+    material: Option<Material>,
+    reject_degenerate_triangles: bool,
+    split_method: SplitMethod,
+}
+
+impl Default for MeshOptions {
+    fn default() -> Self {
+        Self {
+            crease_angle: None,
+            material: None,
+            reject_degenerate_triangles: false,
+            split_method: SplitMethod::Sah,
+        }
+    }
+}
+
+impl MeshOptions {
+    // Used for every triangle instead of the materials from the OBJ's MTL files
+    pub fn material(mut self, material: Material) -> Self {
+        self.material = Some(material);
+        self
+    }
+
+    // Generated normals are only averaged over faces meeting at less than this
+    // angle in degrees, sharper edges keep a hard crease. Without a crease
+    // angle every edge is smoothed, except in STL files, which default to
+    // STL_CREASE_ANGLE. Normals from the file are used as they are.
+    pub fn crease_angle(mut self, degrees: f64) -> Self {
+        self.crease_angle = Some(degrees);
+        self
+    }
+
+    // Fails with TracerError::DegenerateTriangle on zero-area triangles instead of
+    // dropping them and counting them in Mesh::skipped_triangles
+    pub fn reject_degenerate_triangles(mut self) -> Self {
+        self.reject_degenerate_triangles = true;
+        self
+    }
+
+    pub fn split_method(mut self, split_method: SplitMethod) -> Self {
+        self.split_method = split_method;
+        self
+    }
+}
+
+// Per-vertex attributes, all indexed the same way
+struct VertexBuffers {
+    positions: Vec<Vector3>,
+    normals: Vec<Vector3>,
+    texture_coordinates: Vec<[f64; 2]>,
+    colors: Vec<Vector3>,
+}
+
+// Triangle mesh with shared vertex buffers, triangles refer to vertices by index
 pub struct Mesh {
-    triangles: Vec<Arc<WorldObject>>,
-    node: BVHNode,
+    positions: Vec<Vector3>,
+    normals: Vec<Vector3>,
+    // Empty when the mesh isn't textured
+    texture_coordinates: Vec<[f64; 2]>,
+    // Empty without vertex colors
+    colors: Vec<Vector3>,
+    triangles: Vec<[u32; 3]>,
+    // Index into `materials` for every triangle
+    triangle_materials: Vec<u32>,
+    materials: Vec<Material>,
+    // Triangle indices, intersected through the vertex buffers
+    bvh: BVH<u32>,
     bounding_box: AABB,
+    // Zero-area triangles dropped while loading
+    skipped_triangles: usize,
 }
 
 impl Mesh {
-    pub fn from_file(path: String, material: Material) -> Self {
-        let file = File::open(path).unwrap();
-
-        let input = BufReader::new(file);
-        let model: Obj<Position> = load_obj(input).unwrap();
-
-        let vertices = model.vertices;
-
-        let chunked_indices = model.indices.chunks_exact(3);
-
-        let triangles: Vec<Arc<WorldObject>> = chunked_indices
-            .map(|chunk| {
-                let a = vertices[chunk[0] as usize];
-                let b = vertices[chunk[1] as usize];
-                let c = vertices[chunk[2] as usize];
-                let a_vector = position_to_vector(a);
-                let b_vector = position_to_vector(b);
-                let c_vector = position_to_vector(c);
-                Arc::new(WorldObject::Quad(Quad::new_triangle(
-                    a_vector, b_vector, c_vector, material,
-                )))
-            })
+    // Every triangle gets the given material
+    pub fn from_file(path: impl AsRef<Path>, material: Material) -> Result<Self, TracerError> {
+        Self::from_file_with_options(path, MeshOptions::default().material(material))
+    }
+
+    // Loads an OBJ, PLY or binary STL file, picked by the extension. OBJ triangles get
+    // the materials assigned by `usemtl`, unless the options override them.
+    pub fn from_file_with_options(
+        path: impl AsRef<Path>,
+        mut options: MeshOptions,
+    ) -> Result<Self, TracerError> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        let model = match extension.as_deref() {
+            Some("obj") => load_obj(path)?,
+            Some("ply") => load_ply(path)?,
+            Some("stl") => {
+                // STL has no smoothing information, so only shallow edges are smoothed
+                if options.crease_angle.is_none() {
+                    options.crease_angle = Some(STL_CREASE_ANGLE);
+                }
+                load_stl(path)?
+            }
+            _ => {
+                return Err(TracerError::invalid_data(
+                    path,
+                    "unsupported mesh format, expected an .obj, .ply or .stl file",
+                ))
+            }
+        };
+
+        Self::from_model(path, model, options)
+    }
+
+    // Merges the corners of a loaded model into shared vertices. The path is used for
+    // error messages and to find the model's MTL files.
+    pub(crate) fn from_model(
+        path: &Path,
+        mut model: MeshModel,
+        options: MeshOptions,
+    ) -> Result<Self, TracerError> {
+        let face_normals: Vec<Vector3> = model
+            .faces
+            .iter()
+            .map(|face| face_normal(&model.positions, face))
             .collect();
 
-        let node = BVHNode::new(triangles.clone());
+        if let Some(index) = face_normals
+            .iter()
+            .position(|normal| normal.length_squared() == 0.0)
+        {
+            if options.reject_degenerate_triangles {
+                return Err(TracerError::DegenerateTriangle {
+                    path: path.to_path_buf(),
+                    line: model.faces[index].line,
+                    triangle: index + 1,
+                });
+            }
+        }
+
+        let face_count = model.faces.len();
+        let (faces, face_normals): (Vec<Face>, Vec<Vector3>) = model
+            .faces
+            .drain(..)
+            .zip(face_normals)
+            .filter(|(_, normal)| normal.length_squared() > 0.0)
+            .unzip();
+
+        if faces.is_empty() {
+            return Err(TracerError::invalid_data(
+                path,
+                "the file does not contain any faces",
+            ));
+        }
+
+        // Material 0 is for faces without a `usemtl`, the others follow the material names.
+        // Vertex colors multiply the albedo, so the default is white when there are any.
+        let default_albedo = if model.colors.is_empty() {
+            Vector3::new(0.8, 0.8, 0.8)
+        } else {
+            Vector3::new(1.0, 1.0, 1.0)
+        };
+        let default_material = options
+            .material
+            .clone()
+            .unwrap_or_else(|| Material::Lambertian(Lambertian::new(default_albedo)));
+        let mut materials = vec![default_material; model.material_names.len() + 1];
+
+        if options.material.is_none() {
+            let directory = path.parent().unwrap_or(Path::new(""));
+            let mut library = HashMap::new();
+            for file in &model.material_libraries {
+                library.extend(load_mtl(&directory.join(file))?);
+            }
+
+            for (name, material) in model.material_names.iter().zip(&mut materials[1..]) {
+                if let Some(description) = library.get(name) {
+                    *material = description.to_material()?;
+                }
+            }
+        }
+
+        let generated_normals = generate_normals(
+            &model.positions,
+            &faces,
+            &face_normals,
+            options.crease_angle,
+        );
+
+        // Corners that agree on position, texture coordinates, color and normal share a vertex
+        let textured = faces
+            .iter()
+            .any(|face| face.corners.iter().any(|corner| corner.texture.is_some()));
+        let colored = faces
+            .iter()
+            .any(|face| face.corners.iter().any(|corner| corner.color.is_some()));
+        let mut vertex_indices: HashMap<VertexKey, u32> = HashMap::new();
+        let mut positions = vec![];
+        let mut normals = vec![];
+        let mut texture_coordinates = vec![];
+        let mut colors = vec![];
+
+        let mut triangles = Vec::with_capacity(faces.len());
+        let mut triangle_materials = Vec::with_capacity(faces.len());
 
-        let bounding_box = node.bounding_box();
+        for (face, generated) in faces.iter().zip(generated_normals) {
+            let triangle = [0, 1, 2].map(|i| {
+                let corner = face.corners[i];
+                let normal = match corner.normal {
+                    Some(normal) => model.normals[normal],
+                    None => generated[i],
+                };
+                let key = (
+                    corner.position,
+                    corner.texture,
+                    corner.color,
+                    [
+                        normal.x().to_bits(),
+                        normal.y().to_bits(),
+                        normal.z().to_bits(),
+                    ],
+                );
+
+                *vertex_indices.entry(key).or_insert_with(|| {
+                    positions.push(model.positions[corner.position]);
+                    normals.push(unit_vector(normal));
+                    if textured {
+                        let texture = corner.texture.map(|t| model.texture_coordinates[t]);
+                        texture_coordinates.push(texture.unwrap_or([0.0, 0.0]));
+                    }
+                    if colored {
+                        let color = corner.color.map(|c| model.colors[c]);
+                        colors.push(color.unwrap_or(Vector3::new(1.0, 1.0, 1.0)));
+                    }
+                    positions.len() as u32 - 1
+                })
+            });
+
+            triangles.push(triangle);
+            triangle_materials.push(face.material.map_or(0, |index| index as u32 + 1));
+        }
+
+        let vertices = VertexBuffers {
+            positions,
+            normals,
+            texture_coordinates,
+            colors,
+        };
+
+        Ok(Self {
+            skipped_triangles: face_count - faces.len(),
+            ..Self::new(
+                vertices,
+                triangles,
+                triangle_materials,
+                materials,
+                options.split_method,
+            )
+        })
+    }
+
+    fn new(
+        vertices: VertexBuffers,
+        triangles: Vec<[u32; 3]>,
+        triangle_materials: Vec<u32>,
+        materials: Vec<Material>,
+        split_method: SplitMethod,
+    ) -> Self {
+        let VertexBuffers {
+            positions,
+            normals,
+            texture_coordinates,
+            colors,
+        } = vertices;
+
+        let bounding_boxes: Vec<AABB> = triangles
+            .iter()
+            .map(|triangle| {
+                let [a, b, c] = triangle.map(|index| positions[index as usize]);
+                AABB::from_bounding_boxes(AABB::from_points(a, b), AABB::from_points(a, c))
+            })
+            .collect();
+        let bounding_box = bounding_boxes
+            .iter()
+            .fold(AABB::empty(), |bounding_box, triangle| {
+                AABB::from_bounding_boxes(bounding_box, *triangle)
+            });
+
+        let indices = (0..triangles.len() as u32).collect();
+        let bvh = BVH::from_bounding_boxes(indices, bounding_boxes, split_method);
 
         Self {
+            positions,
+            normals,
+            texture_coordinates,
+            colors,
             triangles,
-            node,
+            triangle_materials,
+            materials,
+            bvh,
             bounding_box,
+            skipped_triangles: 0,
         }
     }
+
+    pub fn triangle_count(&self) -> usize {
+        self.triangles.len()
+    }
+
+    pub fn skipped_triangles(&self) -> usize {
+        self.skipped_triangles
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn bvh_stats(&self) -> BVHStats {
+        self.bvh.stats()
+    }
+
+    // Triangles with a Light material as shapes of their own, so they can be sampled as lights
+    pub fn lights(&self) -> Vec<Quad> {
+        self.triangles
+            .iter()
+            .zip(&self.triangle_materials)
+            .map(|(triangle, &material)| (triangle, &self.materials[material as usize]))
+            .filter(|(_, material)| material.is_emissive())
+            .map(|(triangle, material)| {
+                let [a, b, c] = triangle.map(|index| self.positions[index as usize]);
+                Quad::new_triangle(a, b, c, material.clone())
+            })
+            .collect()
+    }
+
+    fn hit_triangle(
+        &self,
+        index: usize,
+        ray: &Ray,
+        watertight_ray: &WatertightRay,
+        ray_t: &Interval,
+    ) -> Option<HitRecord<'_>> {
+        let [a, b, c] = self.triangles[index].map(|vertex| vertex as usize);
+        let (pa, pb, pc) = (self.positions[a], self.positions[b], self.positions[c]);
+
+        let (t, weights) = watertight_ray.intersect(pa, pb, pc)?;
+        if !ray_t.surrounds(t) {
+            return None;
+        }
+
+        let geometric_normal = cross_product(pb - pa, pc - pa);
+        let front_face = dot_product(ray.direction(), geometric_normal) < 0.0;
+
+        let interpolate = |values: [Vector3; 3]| {
+            values[0] * weights[0] + values[1] * weights[1] + values[2] * weights[2]
+        };
+
+        // Keep the shading normal on the same side as the geometry, in case the
+        // vertex normals disagree with the winding order
+        let mut normal = unit_vector(interpolate([
+            self.normals[a],
+            self.normals[b],
+            self.normals[c],
+        ]));
+        if dot_product(normal, geometric_normal) < 0.0 {
+            normal = -normal;
+        }
+        if !front_face {
+            normal = -normal;
+        }
+
+        // Without texture coordinates the barycentric weights of b and c are used
+        let (u, v) = if self.texture_coordinates.is_empty() {
+            (weights[1], weights[2])
+        } else {
+            let [ta, tb, tc] = [a, b, c].map(|vertex| self.texture_coordinates[vertex]);
+            (
+                ta[0] * weights[0] + tb[0] * weights[1] + tc[0] * weights[2],
+                ta[1] * weights[0] + tb[1] * weights[1] + tc[1] * weights[2],
+            )
+        };
+
+        let color = if self.colors.is_empty() {
+            None
+        } else {
+            Some(interpolate([
+                self.colors[a],
+                self.colors[b],
+                self.colors[c],
+            ]))
+        };
+
+        Some(
+            HitRecord::new(
+                ray.at(t),
+                normal,
+                &self.materials[self.triangle_materials[index] as usize],
+                t,
+                front_face,
+                u,
+                v,
+            )
+            .with_vertex_color(color),
+        )
+    }
 }
 
 impl Hittable for Mesh {
-    fn bounding_box(&self) -> crate::aabb::AABB {
+    fn bounding_box(&self) -> AABB {
         self.bounding_box
     }
 
-    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<HitRecord> {
-        self.node.hit(ray, ray_t)
+    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<HitRecord<'_>> {
+        let watertight_ray = WatertightRay::new(ray);
+        self.bvh.hit_with(ray, ray_t, |&index, interval| {
+            self.hit_triangle(index as usize, ray, &watertight_ray, interval)
+        })
     }
 }
 
-fn position_to_vector(position: Position) -> Vector3 {
-    let array = position.position;
-    Vector3::new(array[0].into(), array[1].into(), array[2].into())
+// The cross product's length is twice the triangle area, which gives the weighting
+// when averaging vertex normals
+fn face_normal(positions: &[Vector3], face: &Face) -> Vector3 {
+    let [a, b, c] = face.corners.map(|corner| positions[corner.position]);
+    cross_product(b - a, c - a)
+}
+
+// Area-weighted vertex normals for every face corner. Faces sharing a position
+// contribute when their normals are within the crease angle of the corner's face.
+fn generate_normals(
+    positions: &[Vector3],
+    faces: &[Face],
+    face_normals: &[Vector3],
+    crease_angle: Option<f64>,
+) -> Vec<[Vector3; 3]> {
+    let mut adjacent_faces: Vec<Vec<usize>> = vec![vec![]; positions.len()];
+    for (index, face) in faces.iter().enumerate() {
+        for corner in &face.corners {
+            adjacent_faces[corner.position].push(index);
+        }
+    }
+
+    let minimum_cosine = crease_angle.map(|degrees| degrees.to_radians().cos());
+
+    faces
+        .iter()
+        .zip(face_normals)
+        .map(|(face, &face_normal)| {
+            let face_direction = unit_vector(face_normal);
+
+            face.corners.map(|corner| {
+                let sum = adjacent_faces[corner.position]
+                    .iter()
+                    .map(|&other| face_normals[other])
+                    .filter(|&other| match minimum_cosine {
+                        Some(minimum_cosine) => {
+                            dot_product(face_direction, unit_vector(other)) >= minimum_cosine
+                        }
+                        None => true,
+                    })
+                    .fold(Vector3::zero(), |sum, other| sum + other);
+
+                // Opposite faces sharing a vertex can cancel out
+                if sum.length_squared() == 0.0 {
+                    face_normal
+                } else {
+                    sum
+                }
+            })
+        })
+        .collect()
 }
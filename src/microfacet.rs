@@ -0,0 +1,181 @@
+use std::f64::consts::PI;
+
+use crate::{
+    util::random_unit_float,
+    vec::{cross_product, dot_product, unit_vector, Vector3},
+};
+
+// Below this alpha a surface is treated as perfectly smooth, as the distribution
+// becomes too peaked to evaluate reliably
+const SMOOTH_ALPHA: f64 = 1e-3;
+
+// Trowbridge-Reitz (GGX) distribution of microfacet normals with the Smith shadowing
+// term, in a local frame where the surface normal is +z. Directions point away from the
+// surface.
+#[derive(Debug, Clone, Copy)]
+pub struct TrowbridgeReitz {
+    alpha: f64,
+}
+
+impl TrowbridgeReitz {
+    // Roughness from 0 to 1, squared into alpha so it changes perceptually evenly
+    pub fn new(roughness: f64) -> Self {
+        let roughness = roughness.clamp(0.0, 1.0);
+        Self {
+            alpha: roughness * roughness,
+        }
+    }
+
+    pub fn is_smooth(&self) -> bool {
+        self.alpha < SMOOTH_ALPHA
+    }
+
+    // Density of microfacets facing the given normal, per unit of projected area
+    pub fn d(&self, wm: Vector3) -> f64 {
+        let alpha_squared = self.alpha * self.alpha;
+        let cos_theta_squared = wm.z() * wm.z();
+        let denominator = cos_theta_squared * (alpha_squared - 1.0) + 1.0;
+        alpha_squared / (PI * denominator * denominator)
+    }
+
+    // Fraction of microfacets visible from one direction
+    pub fn g1(&self, w: Vector3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    // Fraction visible from both directions, with height-correlated masking
+    pub fn g(&self, wo: Vector3, wi: Vector3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    fn lambda(&self, w: Vector3) -> f64 {
+        let cos_theta_squared = w.z() * w.z();
+        if cos_theta_squared == 0.0 {
+            return f64::INFINITY;
+        }
+        let tan_theta_squared = (1.0 - cos_theta_squared).max(0.0) / cos_theta_squared;
+        ((1.0 + self.alpha * self.alpha * tan_theta_squared).sqrt() - 1.0) / 2.0
+    }
+
+    // Density of sample_visible_normal() picking the given normal
+    pub fn visible_pdf(&self, wo: Vector3, wm: Vector3) -> f64 {
+        self.g1(wo) / wo.z().abs() * self.d(wm) * dot_product(wo, wm).abs()
+    }
+
+    // Samples a normal among the microfacets visible from wo, following Heitz,
+    // "Sampling the GGX Distribution of Visible Normals" (2018)
+    pub fn sample_visible_normal(&self, wo: Vector3) -> Vector3 {
+        // Stretch to the hemisphere configuration where alpha is 1
+        let wh = unit_vector(Vector3::new(
+            self.alpha * wo.x(),
+            self.alpha * wo.y(),
+            wo.z(),
+        ));
+        let length_squared = wh.x() * wh.x() + wh.y() * wh.y();
+        let t1 = if length_squared > 0.0 {
+            Vector3::new(-wh.y(), wh.x(), 0.0) / length_squared.sqrt()
+        } else {
+            Vector3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = cross_product(wh, t1);
+
+        // Uniform point on a disk, squashed onto the visible part of the hemisphere
+        let radius = random_unit_float().sqrt();
+        let phi = 2.0 * PI * random_unit_float();
+        let p1 = radius * phi.cos();
+        let s = 0.5 * (1.0 + wh.z());
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * radius * phi.sin();
+        let p3 = (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+
+        let nh = t1 * p1 + t2 * p2 + wh * p3;
+        unit_vector(Vector3::new(
+            self.alpha * nh.x(),
+            self.alpha * nh.y(),
+            nh.z().max(1e-6),
+        ))
+    }
+}
+
+// Unpolarized reflectance of a dielectric boundary, where eta is the ratio of the
+// refraction index on the far side to the one on the near side
+pub fn fresnel_dielectric(cos_theta_i: f64, eta: f64) -> f64 {
+    let cos_theta_i = cos_theta_i.clamp(0.0, 1.0);
+    let sin_theta_t_squared = (1.0 - cos_theta_i * cos_theta_i) / (eta * eta);
+    // Total internal reflection
+    if sin_theta_t_squared >= 1.0 {
+        return 1.0;
+    }
+    let cos_theta_t = (1.0 - sin_theta_t_squared).sqrt();
+
+    let parallel = (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+    let perpendicular = (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+    (parallel * parallel + perpendicular * perpendicular) / 2.0
+}
+
+// Reflectance of a conductor with the complex refraction index eta + ik, per channel
+pub fn fresnel_conductor(cos_theta_i: f64, eta: Vector3, k: Vector3) -> Vector3 {
+    let cos_theta_i = cos_theta_i.clamp(0.0, 1.0);
+    let [r, g, b] =
+        [0, 1, 2].map(|axis| fresnel_conductor_channel(cos_theta_i, eta.axis(axis), k.axis(axis)));
+    Vector3::new(r, g, b)
+}
+
+fn fresnel_conductor_channel(cos_theta: f64, eta: f64, k: f64) -> f64 {
+    let cos_squared = cos_theta * cos_theta;
+    let sin_squared = 1.0 - cos_squared;
+    let eta_squared = eta * eta;
+    let k_squared = k * k;
+
+    let t0 = eta_squared - k_squared - sin_squared;
+    let a_squared_plus_b_squared = (t0 * t0 + 4.0 * eta_squared * k_squared).sqrt();
+    let t1 = a_squared_plus_b_squared + cos_squared;
+    let a = (0.5 * (a_squared_plus_b_squared + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cos_theta * a;
+    let perpendicular = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos_squared * a_squared_plus_b_squared + sin_squared * sin_squared;
+    let t4 = t2 * sin_squared;
+    let parallel = perpendicular * (t3 - t4) / (t3 + t4);
+
+    (parallel + perpendicular) / 2.0
+}
+
+// Mirrors w around the normal, both pointing away from the surface
+pub fn reflect(w: Vector3, normal: Vector3) -> Vector3 {
+    normal * (2.0 * dot_product(w, normal)) - w
+}
+
+// Bends w through a boundary with the normal on its side, with eta as in
+// fresnel_dielectric(). None on total internal reflection.
+pub fn refract(w: Vector3, normal: Vector3, eta: f64) -> Option<Vector3> {
+    let cos_theta_i = dot_product(w, normal);
+    let sin_theta_t_squared = (1.0 - cos_theta_i * cos_theta_i).max(0.0) / (eta * eta);
+    if sin_theta_t_squared >= 1.0 {
+        return None;
+    }
+    let cos_theta_t = (1.0 - sin_theta_t_squared).sqrt();
+
+    Some(-w / eta + normal * (cos_theta_i / eta - cos_theta_t))
+}
+
+// Microfacet normal that reflects or refracts wo into wi, None when no microfacet facing
+// wo could have done it
+pub fn microfacet_normal(wo: Vector3, wi: Vector3, eta: f64) -> Option<Vector3> {
+    if wo.z() <= 0.0 || wi.z() == 0.0 {
+        return None;
+    }
+
+    let half = if wi.z() > 0.0 { wo + wi } else { wi * eta + wo };
+    if half.length_squared() == 0.0 {
+        return None;
+    }
+    let mut wm = unit_vector(half);
+    if wm.z() < 0.0 {
+        wm = -wm;
+    }
+
+    if dot_product(wm, wi) * wi.z() < 0.0 || dot_product(wm, wo) <= 0.0 {
+        return None;
+    }
+    Some(wm)
+}
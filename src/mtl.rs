@@ -0,0 +1,246 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::TracerError,
+    material::{Dielectric, Lambertian, Light, Material, Metal},
+    principled::Principled,
+    texture::{ImageTexture, Texture},
+    vec::Vector3,
+};
+
+// MTL's default diffuse color
+const DEFAULT_DIFFUSE: f64 = 0.8;
+
+// The subset of a Wavefront MTL material that maps onto the crate's materials
+#[derive(Debug, Clone, Default)]
+pub struct MtlMaterial {
+    diffuse: Option<Vector3>,
+    specular: Option<Vector3>,
+    emission: Option<Vector3>,
+    specular_exponent: Option<f64>,
+    refraction_index: Option<f64>,
+    dissolve: Option<f64>,
+    // Physically based extension written by Blender and other exporters
+    roughness: Option<f64>,
+    metallic: Option<f64>,
+    sheen: Option<f64>,
+    clearcoat: Option<f64>,
+    clearcoat_roughness: Option<f64>,
+    // Resolved relative to the MTL file
+    diffuse_map: Option<PathBuf>,
+    roughness_map: Option<PathBuf>,
+    metallic_map: Option<PathBuf>,
+}
+
+impl MtlMaterial {
+    // Picks the closest material: emissive surfaces become lights, sampled triangle by
+    // triangle through Mesh::lights(), and materials with physically based parameters
+    // principled ones. Otherwise transparent ones become
+    // dielectrics, mostly specular ones metals and everything else is diffuse.
+    pub fn to_material(&self) -> Result<Material, TracerError> {
+        if let Some(emission) = self
+            .emission
+            .filter(|emission| max_component(*emission) > 0.0)
+        {
+            return Ok(Material::Light(Light::new(emission)));
+        }
+
+        // Plenty of exporters write Ni 1 for everything, which would make glass invisible
+        let refraction_index = self
+            .refraction_index
+            .filter(|&index| index > 1.0)
+            .unwrap_or(1.5);
+
+        if self.is_physically_based() {
+            return self.to_principled(refraction_index);
+        }
+
+        if self.dissolve.is_some_and(|dissolve| dissolve < 1.0) {
+            return Ok(Material::Dielectric(Dielectric::new(refraction_index)));
+        }
+
+        let diffuse = self.diffuse();
+
+        if let Some(specular) = self.specular {
+            if self.diffuse_map.is_none() && max_component(specular) > max_component(diffuse) {
+                // Blinn-Phong exponent to an approximate roughness
+                let exponent = self.specular_exponent.unwrap_or(0.0).max(0.0);
+                let fuzz = (2.0 / (exponent + 2.0)).sqrt();
+                return Ok(Material::Metal(Metal::new(specular, fuzz)));
+            }
+        }
+
+        let albedo = match &self.diffuse_map {
+            Some(path) => load_texture(path, false)?,
+            None => Texture::from(diffuse),
+        };
+
+        Ok(Material::Lambertian(Lambertian::from_texture(albedo)))
+    }
+
+    fn is_physically_based(&self) -> bool {
+        [
+            self.roughness,
+            self.metallic,
+            self.sheen,
+            self.clearcoat,
+            self.clearcoat_roughness,
+        ]
+        .iter()
+        .any(Option::is_some)
+            || self.roughness_map.is_some()
+            || self.metallic_map.is_some()
+    }
+
+    // Maps are used instead of the matching values, transparency becomes transmission
+    fn to_principled(&self, refraction_index: f64) -> Result<Material, TracerError> {
+        let base_color = match &self.diffuse_map {
+            Some(path) => load_texture(path, false)?,
+            None => Texture::from(self.diffuse()),
+        };
+        let parameter = |map: &Option<PathBuf>, value: Option<f64>, default: f64| match map {
+            Some(path) => load_texture(path, true),
+            None => Ok(Texture::from(value.unwrap_or(default))),
+        };
+
+        let principled = Principled::from_texture(base_color)
+            .roughness(parameter(&self.roughness_map, self.roughness, 0.5)?)
+            .metallic(parameter(&self.metallic_map, self.metallic, 0.0)?)
+            .sheen(self.sheen.unwrap_or(0.0).into())
+            .clearcoat(self.clearcoat.unwrap_or(0.0).into())
+            .clearcoat_roughness(self.clearcoat_roughness.unwrap_or(0.1).into())
+            .transmission((1.0 - self.dissolve.unwrap_or(1.0)).into())
+            .refraction_index(refraction_index);
+
+        Ok(Material::Principled(Box::new(principled)))
+    }
+
+    fn diffuse(&self) -> Vector3 {
+        self.diffuse.unwrap_or(Vector3::new(
+            DEFAULT_DIFFUSE,
+            DEFAULT_DIFFUSE,
+            DEFAULT_DIFFUSE,
+        ))
+    }
+}
+
+// Colors are sRGB encoded, data like roughness is stored linearly
+fn load_texture(path: &Path, linear: bool) -> Result<Texture, TracerError> {
+    let image = if linear {
+        ImageTexture::from_linear_file(path)
+    } else {
+        ImageTexture::from_file(path)
+    };
+    let image = image.map_err(|source| TracerError::Image {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    Ok(Texture::Image(image))
+}
+
+// Parses the materials of an MTL file, statements that don't affect the mapping are ignored
+pub fn load_mtl(path: &Path) -> Result<HashMap<String, MtlMaterial>, TracerError> {
+    let source = fs::read_to_string(path).map_err(|e| TracerError::io(path, e))?;
+    let directory = path.parent().unwrap_or(Path::new(""));
+
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, MtlMaterial)> = None;
+
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let error = |message: &str| TracerError::parse(path, line_number, message);
+
+        let mut tokens = line.split_whitespace();
+        let Some(statement) = tokens.next() else {
+            continue;
+        };
+        let arguments: Vec<&str> = tokens.collect();
+
+        if statement.starts_with('#') {
+            continue;
+        }
+
+        if statement == "newmtl" {
+            let name = arguments.join(" ");
+            if name.is_empty() {
+                return Err(error("expected a material name"));
+            }
+            if let Some((name, material)) = current.replace((name, MtlMaterial::default())) {
+                materials.insert(name, material);
+            }
+            continue;
+        }
+
+        let Some((_, material)) = current.as_mut() else {
+            continue;
+        };
+
+        let invalid = || error(&format!("invalid {} value", statement));
+        match statement {
+            "Kd" => material.diffuse = Some(parse_color(&arguments).ok_or_else(invalid)?),
+            "Ks" => material.specular = Some(parse_color(&arguments).ok_or_else(invalid)?),
+            "Ke" => material.emission = Some(parse_color(&arguments).ok_or_else(invalid)?),
+            "Ns" => {
+                material.specular_exponent = Some(parse_number(&arguments).ok_or_else(invalid)?)
+            }
+            "Ni" => material.refraction_index = Some(parse_number(&arguments).ok_or_else(invalid)?),
+            "d" => material.dissolve = Some(parse_number(&arguments).ok_or_else(invalid)?),
+            "Tr" => material.dissolve = Some(1.0 - parse_number(&arguments).ok_or_else(invalid)?),
+            "Pr" => material.roughness = Some(parse_number(&arguments).ok_or_else(invalid)?),
+            "Pm" => material.metallic = Some(parse_number(&arguments).ok_or_else(invalid)?),
+            "Ps" => material.sheen = Some(parse_number(&arguments).ok_or_else(invalid)?),
+            "Pc" => material.clearcoat = Some(parse_number(&arguments).ok_or_else(invalid)?),
+            "Pcr" => {
+                material.clearcoat_roughness = Some(parse_number(&arguments).ok_or_else(invalid)?)
+            }
+            "map_Kd" | "map_Pr" | "map_Pm" => {
+                // Options like `-s 1 1 1` come before the file name
+                let file = arguments
+                    .last()
+                    .ok_or_else(|| error("expected a texture file"))?;
+                let path = Some(directory.join(file));
+                match statement {
+                    "map_Kd" => material.diffuse_map = path,
+                    "map_Pr" => material.roughness_map = path,
+                    _ => material.metallic_map = path,
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if let Some((name, material)) = current {
+        materials.insert(name, material);
+    }
+
+    Ok(materials)
+}
+
+fn parse_number(arguments: &[&str]) -> Option<f64> {
+    match arguments {
+        [value] => value.parse().ok(),
+        _ => None,
+    }
+}
+
+// A single value is used for all three channels
+fn parse_color(arguments: &[&str]) -> Option<Vector3> {
+    let values: Vec<f64> = arguments
+        .iter()
+        .map(|argument| argument.parse().ok())
+        .collect::<Option<_>>()?;
+
+    match values[..] {
+        [value] => Some(Vector3::new(value, value, value)),
+        [r, g, b] => Some(Vector3::new(r, g, b)),
+        _ => None,
+    }
+}
+
+fn max_component(color: Vector3) -> f64 {
+    color.x().max(color.y()).max(color.z())
+}
@@ -0,0 +1,159 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use crate::{
+    error::TracerError,
+    mesh::{Corner, Face, MeshModel},
+    vec::Vector3,
+};
+
+// Reads a Wavefront OBJ file, splitting its polygons into triangle fans
+pub(crate) fn load_obj(path: &Path) -> Result<MeshModel, TracerError> {
+    let source = fs::read_to_string(path).map_err(|e| TracerError::io(path, e))?;
+    parse_obj(&source, path)
+}
+
+// Only vertex data, faces and materials are read, other statements are ignored
+fn parse_obj(source: &str, path: &Path) -> Result<MeshModel, TracerError> {
+    let mut model = MeshModel::default();
+    let mut material_indices: HashMap<String, usize> = HashMap::new();
+    let mut material = None;
+
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let error = |message: String| TracerError::parse(path, line_number, message);
+
+        let mut tokens = line.split_whitespace();
+        let Some(statement) = tokens.next() else {
+            continue;
+        };
+        let arguments: Vec<&str> = tokens.collect();
+
+        match statement {
+            "v" => {
+                let values = parse_numbers(&arguments, 3..=4).map_err(error)?;
+                model
+                    .positions
+                    .push(Vector3::new(values[0], values[1], values[2]));
+            }
+            "vt" => {
+                let values = parse_numbers(&arguments, 1..=3).map_err(error)?;
+                model
+                    .texture_coordinates
+                    .push([values[0], values.get(1).copied().unwrap_or(0.0)]);
+            }
+            "vn" => {
+                let values = parse_numbers(&arguments, 3..=3).map_err(error)?;
+                model
+                    .normals
+                    .push(Vector3::new(values[0], values[1], values[2]));
+            }
+            "f" => {
+                if arguments.len() < 3 {
+                    return Err(error("a face needs at least 3 vertices".to_string()));
+                }
+                let corners = arguments
+                    .iter()
+                    .map(|argument| parse_corner(argument, &model, path, line_number))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                // Fan around the first corner
+                for i in 1..corners.len() - 1 {
+                    model.faces.push(Face {
+                        corners: [corners[0], corners[i], corners[i + 1]],
+                        material,
+                        line: Some(line_number),
+                    });
+                }
+            }
+            "usemtl" => {
+                let name = arguments.join(" ");
+                let next_index = material_indices.len();
+                let index = *material_indices.entry(name.clone()).or_insert_with(|| {
+                    model.material_names.push(name);
+                    next_index
+                });
+                material = Some(index);
+            }
+            "mtllib" => model
+                .material_libraries
+                .extend(arguments.iter().map(|argument| argument.to_string())),
+            _ => {}
+        }
+    }
+
+    Ok(model)
+}
+
+fn parse_numbers(
+    arguments: &[&str],
+    count: std::ops::RangeInclusive<usize>,
+) -> Result<Vec<f64>, String> {
+    if !count.contains(&arguments.len()) {
+        return Err(format!(
+            "expected {} to {} numbers, found {}",
+            count.start(),
+            count.end(),
+            arguments.len()
+        ));
+    }
+
+    arguments
+        .iter()
+        .map(|argument| {
+            argument
+                .parse()
+                .map_err(|_| format!("`{}` is not a number", argument))
+        })
+        .collect()
+}
+
+// Parses `v`, `v/vt`, `v//vn` or `v/vt/vn`, resolving the 1-based and relative
+// indices to positions in the lists read so far
+fn parse_corner(
+    argument: &str,
+    model: &MeshModel,
+    path: &Path,
+    line: usize,
+) -> Result<Corner, TracerError> {
+    let mut parts = argument.split('/');
+
+    let mut index = |attribute: &'static str, count: usize| -> Result<Option<usize>, TracerError> {
+        let Some(part) = parts.next().filter(|part| !part.is_empty()) else {
+            return Ok(None);
+        };
+        let index: i64 = part.parse().map_err(|_| {
+            TracerError::parse(path, line, format!("`{}` is not a vertex index", part))
+        })?;
+
+        let resolved = if index < 0 {
+            count as i64 + index
+        } else {
+            index - 1
+        };
+        if resolved < 0 || resolved >= count as i64 {
+            return Err(TracerError::IndexOutOfRange {
+                path: path.to_path_buf(),
+                line: Some(line),
+                face: model.faces.len() + 1,
+                attribute,
+                index,
+                count,
+            });
+        }
+
+        Ok(Some(resolved as usize))
+    };
+
+    let position = index("position", model.positions.len())?.ok_or_else(|| {
+        TracerError::parse(path, line, format!("`{}` has no position index", argument))
+    })?;
+    let texture = index("texture coordinate", model.texture_coordinates.len())?;
+    let normal = index("normal", model.normals.len())?;
+
+    Ok(Corner {
+        position,
+        texture,
+        normal,
+        color: None,
+    })
+}
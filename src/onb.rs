@@ -0,0 +1,51 @@
+use crate::vec::{cross_product, dot_product, unit_vector, Vector3};
+
+// Orthonormal basis around a normal, used to move between local and world space
+#[derive(Debug, Clone, Copy)]
+pub struct OrthonormalBasis {
+    u: Vector3,
+    v: Vector3,
+    w: Vector3,
+}
+
+impl OrthonormalBasis {
+    pub fn new(normal: Vector3) -> Self {
+        let w = unit_vector(normal);
+        // Any axis that isn't close to parallel with w works as a starting point
+        let a = if w.x().abs() > 0.9 {
+            Vector3::new(0.0, 1.0, 0.0)
+        } else {
+            Vector3::new(1.0, 0.0, 0.0)
+        };
+        let v = unit_vector(cross_product(w, a));
+        let u = cross_product(w, v);
+
+        Self { u, v, w }
+    }
+
+    pub fn u(&self) -> Vector3 {
+        self.u
+    }
+
+    pub fn v(&self) -> Vector3 {
+        self.v
+    }
+
+    pub fn w(&self) -> Vector3 {
+        self.w
+    }
+
+    // Local coordinates to world space, with z along the normal
+    pub fn transform(&self, local: Vector3) -> Vector3 {
+        (self.u * local.x()) + (self.v * local.y()) + (self.w * local.z())
+    }
+
+    // World space to local coordinates
+    pub fn to_local(&self, world: Vector3) -> Vector3 {
+        Vector3::new(
+            dot_product(world, self.u),
+            dot_product(world, self.v),
+            dot_product(world, self.w),
+        )
+    }
+}
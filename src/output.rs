@@ -0,0 +1,95 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use image::{ImageFormat, ImageResult, Rgb32FImage, RgbImage};
+
+use crate::vec::Vector3;
+
+// Writes a packed RGB frame buffer to disk, picking the format from the file extension.
+// Anything that isn't .ppm is written as PNG.
+pub fn save(path: &Path, width: u32, height: u32, frame_buffer: &[u32]) -> ImageResult<()> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) if extension.eq_ignore_ascii_case("ppm") => {
+            write_ppm(path, width, height, frame_buffer)
+        }
+        _ => write_png(path, width, height, frame_buffer),
+    }
+}
+
+pub fn write_png(path: &Path, width: u32, height: u32, frame_buffer: &[u32]) -> ImageResult<()> {
+    let image = RgbImage::from_raw(width, height, unpack_rgb(frame_buffer))
+        .expect("frame buffer size does not match image dimensions");
+    image.save_with_format(path, ImageFormat::Png)
+}
+
+// Binary PPM (P6), no dependencies needed to read it back.
+pub fn write_ppm(path: &Path, width: u32, height: u32, frame_buffer: &[u32]) -> ImageResult<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write!(writer, "P6\n{} {}\n255\n", width, height)?;
+    writer.write_all(&unpack_rgb(frame_buffer))?;
+    writer.flush()?;
+    Ok(())
+}
+
+// Unpack 0x00RRGGBB pixels into a flat RGB byte buffer
+fn unpack_rgb(frame_buffer: &[u32]) -> Vec<u8> {
+    frame_buffer
+        .iter()
+        .flat_map(|pixel| {
+            [
+                ((pixel >> 16) & 0xFF) as u8,
+                ((pixel >> 8) & 0xFF) as u8,
+                (pixel & 0xFF) as u8,
+            ]
+        })
+        .collect()
+}
+
+// Writes a linear radiance buffer to disk without any clamping or gamma.
+// .hdr is written as Radiance RGBE, anything else as OpenEXR.
+pub fn save_hdr(path: &Path, width: u32, height: u32, frame_buffer: &[Vector3]) -> ImageResult<()> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) if extension.eq_ignore_ascii_case("hdr") => {
+            write_radiance_hdr(path, width, height, frame_buffer)
+        }
+        _ => write_exr(path, width, height, frame_buffer),
+    }
+}
+
+pub fn write_exr(
+    path: &Path,
+    width: u32,
+    height: u32,
+    frame_buffer: &[Vector3],
+) -> ImageResult<()> {
+    to_rgb32f(width, height, frame_buffer).save_with_format(path, ImageFormat::OpenExr)
+}
+
+pub fn write_radiance_hdr(
+    path: &Path,
+    width: u32,
+    height: u32,
+    frame_buffer: &[Vector3],
+) -> ImageResult<()> {
+    to_rgb32f(width, height, frame_buffer).save_with_format(path, ImageFormat::Hdr)
+}
+
+// Returns whether the path names one of the high dynamic range formats
+pub fn is_hdr_path(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|extension| extension.to_str()),
+        Some(extension) if extension.eq_ignore_ascii_case("exr") || extension.eq_ignore_ascii_case("hdr")
+    )
+}
+
+fn to_rgb32f(width: u32, height: u32, frame_buffer: &[Vector3]) -> Rgb32FImage {
+    let pixels = frame_buffer
+        .iter()
+        .flat_map(|color| [color.x() as f32, color.y() as f32, color.z() as f32])
+        .collect();
+    Rgb32FImage::from_raw(width, height, pixels)
+        .expect("frame buffer size does not match image dimensions")
+}
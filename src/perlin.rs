@@ -0,0 +1,104 @@
+use rand::seq::SliceRandom;
+
+use crate::vec::{dot_product, Vector3};
+
+const POINT_COUNT: usize = 256;
+
+// Perlin gradient noise, see https://raytracing.github.io/books/RayTracingTheNextWeek.html
+#[derive(Debug)]
+pub struct Perlin {
+    random_vectors: Vec<Vector3>,
+    permutation_x: Vec<usize>,
+    permutation_y: Vec<usize>,
+    permutation_z: Vec<usize>,
+}
+
+impl Perlin {
+    pub fn new() -> Self {
+        let random_vectors = (0..POINT_COUNT)
+            .map(|_| Vector3::random_unit_vector())
+            .collect();
+
+        Self {
+            random_vectors,
+            permutation_x: generate_permutation(),
+            permutation_y: generate_permutation(),
+            permutation_z: generate_permutation(),
+        }
+    }
+
+    // Smooth noise in [-1, 1]
+    pub fn noise(&self, point: Vector3) -> f64 {
+        let u = point.x() - point.x().floor();
+        let v = point.y() - point.y().floor();
+        let w = point.z() - point.z().floor();
+
+        let i = point.x().floor() as i64;
+        let j = point.y().floor() as i64;
+        let k = point.z().floor() as i64;
+
+        let mut corners = [[[Vector3::zero(); 2]; 2]; 2];
+        for (di, plane) in corners.iter_mut().enumerate() {
+            for (dj, row) in plane.iter_mut().enumerate() {
+                for (dk, corner) in row.iter_mut().enumerate() {
+                    let index = self.permutation_x[((i + di as i64) & 255) as usize]
+                        ^ self.permutation_y[((j + dj as i64) & 255) as usize]
+                        ^ self.permutation_z[((k + dk as i64) & 255) as usize];
+                    *corner = self.random_vectors[index];
+                }
+            }
+        }
+
+        trilinear_interpolation(&corners, u, v, w)
+    }
+
+    // Sum of noise octaves, each with double the frequency and half the weight
+    pub fn turbulence(&self, point: Vector3, depth: u32) -> f64 {
+        let mut accumulated = 0.0;
+        let mut point = point;
+        let mut weight = 1.0;
+
+        for _ in 0..depth {
+            accumulated += weight * self.noise(point);
+            weight *= 0.5;
+            point = point * 2.0;
+        }
+
+        accumulated.abs()
+    }
+}
+
+impl Default for Perlin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn generate_permutation() -> Vec<usize> {
+    let mut permutation: Vec<usize> = (0..POINT_COUNT).collect();
+    permutation.shuffle(&mut rand::thread_rng());
+    permutation
+}
+
+fn trilinear_interpolation(corners: &[[[Vector3; 2]; 2]; 2], u: f64, v: f64, w: f64) -> f64 {
+    // Hermite smoothing to avoid grid artifacts
+    let uu = u * u * (3.0 - 2.0 * u);
+    let vv = v * v * (3.0 - 2.0 * v);
+    let ww = w * w * (3.0 - 2.0 * w);
+
+    let mut accumulated = 0.0;
+    for (i, plane) in corners.iter().enumerate() {
+        for (j, row) in plane.iter().enumerate() {
+            for (k, corner) in row.iter().enumerate() {
+                let (fi, fj, fk) = (i as f64, j as f64, k as f64);
+                let weight = Vector3::new(u - fi, v - fj, w - fk);
+                accumulated += (fi * uu + (1.0 - fi) * (1.0 - uu))
+                    * (fj * vv + (1.0 - fj) * (1.0 - vv))
+                    * (fk * ww + (1.0 - fk) * (1.0 - ww))
+                    * dot_product(*corner, weight);
+            }
+        }
+    }
+
+    accumulated
+}
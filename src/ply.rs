@@ -0,0 +1,532 @@
+use std::{fs, path::Path, str::Lines};
+
+use crate::{
+    error::TracerError,
+    mesh::{Corner, Face, MeshModel},
+    tonemap::srgb_to_linear,
+    vec::Vector3,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Scalar {
+    Int8,
+    Uint8,
+    Int16,
+    Uint16,
+    Int32,
+    Uint32,
+    Float32,
+    Float64,
+}
+
+impl Scalar {
+    fn from_name(name: &str) -> Option<Self> {
+        let scalar = match name {
+            "char" | "int8" => Scalar::Int8,
+            "uchar" | "uint8" => Scalar::Uint8,
+            "short" | "int16" => Scalar::Int16,
+            "ushort" | "uint16" => Scalar::Uint16,
+            "int" | "int32" => Scalar::Int32,
+            "uint" | "uint32" => Scalar::Uint32,
+            "float" | "float32" => Scalar::Float32,
+            "double" | "float64" => Scalar::Float64,
+            _ => return None,
+        };
+        Some(scalar)
+    }
+
+    // Largest value of the integer types, which scales integer colors to [0, 1]
+    fn maximum(self) -> Option<f64> {
+        match self {
+            Scalar::Int8 => Some(i8::MAX as f64),
+            Scalar::Uint8 => Some(u8::MAX as f64),
+            Scalar::Int16 => Some(i16::MAX as f64),
+            Scalar::Uint16 => Some(u16::MAX as f64),
+            Scalar::Int32 => Some(i32::MAX as f64),
+            Scalar::Uint32 => Some(u32::MAX as f64),
+            Scalar::Float32 | Scalar::Float64 => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum PropertyType {
+    Scalar(Scalar),
+    // A count followed by that many items
+    List { count: Scalar, item: Scalar },
+}
+
+#[derive(Debug)]
+struct Property {
+    name: String,
+    kind: PropertyType,
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl Element {
+    // First scalar property with one of the names
+    fn scalar(&self, names: &[&str]) -> Option<(usize, Scalar)> {
+        self.properties
+            .iter()
+            .enumerate()
+            .find_map(|(index, property)| match property.kind {
+                PropertyType::Scalar(scalar) if names.contains(&property.name.as_str()) => {
+                    Some((index, scalar))
+                }
+                _ => None,
+            })
+    }
+
+    fn list(&self, names: &[&str]) -> Option<usize> {
+        self.properties.iter().position(|property| {
+            matches!(property.kind, PropertyType::List { .. })
+                && names.contains(&property.name.as_str())
+        })
+    }
+}
+
+struct Header {
+    format: Format,
+    elements: Vec<Element>,
+    line_count: usize,
+    // Bytes up to and including the `end_header` line
+    length: usize,
+}
+
+// Reads a Stanford PLY file in ASCII or binary format. Vertex normals, colors and
+// texture coordinates are kept when the vertices have them, polygons are split into
+// triangle fans and any other elements are skipped.
+pub(crate) fn load_ply(path: &Path) -> Result<MeshModel, TracerError> {
+    let bytes = fs::read(path).map_err(|e| TracerError::io(path, e))?;
+    let header = parse_header(&bytes, path)?;
+    let data = &bytes[header.length..];
+
+    let mut body = match header.format {
+        Format::Ascii => Body::Ascii {
+            lines: std::str::from_utf8(data)
+                .map_err(|_| TracerError::invalid_data(path, "the data is not valid ASCII"))?
+                .lines(),
+            line_number: header.line_count,
+        },
+        Format::BinaryLittleEndian | Format::BinaryBigEndian => Body::Binary {
+            bytes: data,
+            position: 0,
+            big_endian: header.format == Format::BinaryBigEndian,
+        },
+    };
+
+    let vertex_element = header
+        .elements
+        .iter()
+        .find(|element| element.name == "vertex");
+    let vertices = vertex_element
+        .map(|element| VertexReader::new(element, path))
+        .transpose()?;
+    let vertex_count = vertex_element.map_or(0, |element| element.count);
+    let attributes = vertices
+        .as_ref()
+        .map_or(Attributes::default(), VertexReader::attributes);
+
+    let mut model = MeshModel::default();
+    let mut values = vec![];
+    let mut starts = vec![];
+
+    for element in &header.elements {
+        match (element.name.as_str(), &vertices) {
+            ("vertex", Some(reader)) => {
+                for _ in 0..element.count {
+                    body.read(element, &mut values, &mut starts, path)?;
+                    reader.read(&values, &starts, &mut model);
+                }
+            }
+            ("face", _) => {
+                let indices = element
+                    .list(&["vertex_indices", "vertex_index"])
+                    .ok_or_else(|| {
+                        TracerError::invalid_data(path, "the face element has no vertex_indices")
+                    })?;
+
+                for number in 1..=element.count {
+                    let line = body.read(element, &mut values, &mut starts, path)?;
+                    let start = starts[indices];
+                    let count = values[start] as usize;
+                    let polygon = &values[start + 1..start + 1 + count];
+
+                    if count < 3 {
+                        let message = "a face needs at least 3 vertices";
+                        return Err(match line {
+                            Some(line) => TracerError::parse(path, line, message),
+                            None => TracerError::invalid_data(
+                                path,
+                                format!("face {}: {}", number, message),
+                            ),
+                        });
+                    }
+
+                    let corners = polygon
+                        .iter()
+                        .map(|&index| {
+                            if index < 0.0 || index >= vertex_count as f64 {
+                                return Err(TracerError::IndexOutOfRange {
+                                    path: path.to_path_buf(),
+                                    line,
+                                    face: number,
+                                    attribute: "vertex",
+                                    index: index as i64,
+                                    count: vertex_count,
+                                });
+                            }
+                            Ok(attributes.corner(index as usize))
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+
+                    // Fan around the first corner
+                    for i in 1..corners.len() - 1 {
+                        model.faces.push(Face {
+                            corners: [corners[0], corners[i], corners[i + 1]],
+                            material: None,
+                            line,
+                        });
+                    }
+                }
+            }
+            _ => {
+                for _ in 0..element.count {
+                    body.read(element, &mut values, &mut starts, path)?;
+                }
+            }
+        }
+    }
+
+    Ok(model)
+}
+
+fn parse_header(bytes: &[u8], path: &Path) -> Result<Header, TracerError> {
+    let mut format = None;
+    let mut elements: Vec<Element> = vec![];
+    let mut position = 0;
+    let mut line_number = 0;
+
+    loop {
+        let Some(end) = bytes[position..].iter().position(|&byte| byte == b'\n') else {
+            return Err(TracerError::invalid_data(
+                path,
+                "the header has no end_header line",
+            ));
+        };
+        let line = String::from_utf8_lossy(&bytes[position..position + end]);
+        position += end + 1;
+        line_number += 1;
+
+        let error = |message: String| TracerError::parse(path, line_number, message);
+        let scalar = |name: &str| {
+            Scalar::from_name(name).ok_or_else(|| error(format!("unknown type `{}`", name)))
+        };
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+
+        if line_number == 1 {
+            if tokens != ["ply"] {
+                return Err(TracerError::invalid_data(path, "not a PLY file"));
+            }
+            continue;
+        }
+
+        match tokens.as_slice() {
+            ["format", name, _version] => {
+                format = Some(match *name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => return Err(error(format!("unknown format `{}`", name))),
+                });
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| error(format!("`{}` is not an element count", count)))?,
+                properties: vec![],
+            }),
+            ["property", rest @ ..] => {
+                let (kind, name) = match rest {
+                    ["list", count, item, name] => (
+                        PropertyType::List {
+                            count: scalar(count)?,
+                            item: scalar(item)?,
+                        },
+                        name,
+                    ),
+                    [kind, name] => (PropertyType::Scalar(scalar(kind)?), name),
+                    _ => return Err(error("malformed property".to_string())),
+                };
+                let element = elements
+                    .last_mut()
+                    .ok_or_else(|| error("property before the first element".to_string()))?;
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    kind,
+                });
+            }
+            ["end_header"] => break,
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            _ => return Err(error(format!("unexpected `{}`", line.trim()))),
+        }
+    }
+
+    let format =
+        format.ok_or_else(|| TracerError::parse(path, line_number, "the header has no format"))?;
+
+    Ok(Header {
+        format,
+        elements,
+        line_count: line_number,
+        length: position,
+    })
+}
+
+// Vertex attributes besides the position, which face corners refer to by the vertex index
+#[derive(Debug, Default, Clone, Copy)]
+struct Attributes {
+    texture: bool,
+    normal: bool,
+    color: bool,
+}
+
+impl Attributes {
+    fn corner(&self, index: usize) -> Corner {
+        Corner {
+            position: index,
+            texture: self.texture.then_some(index),
+            normal: self.normal.then_some(index),
+            color: self.color.then_some(index),
+        }
+    }
+}
+
+// Scalar properties of the vertex element that make up its attributes
+struct VertexReader {
+    position: [usize; 3],
+    normal: Option<[usize; 3]>,
+    // Color channels with the integer range to normalize by
+    color: Option<[(usize, Option<f64>); 3]>,
+    texture: Option<[usize; 2]>,
+}
+
+impl VertexReader {
+    fn new(element: &Element, path: &Path) -> Result<Self, TracerError> {
+        let find = |names: &[&[&str]]| -> Option<Vec<(usize, Scalar)>> {
+            names.iter().map(|names| element.scalar(names)).collect()
+        };
+        let indices = |found: Vec<(usize, Scalar)>| -> Vec<usize> {
+            found.into_iter().map(|(index, _)| index).collect()
+        };
+
+        let position = find(&[&["x"], &["y"], &["z"]]).ok_or_else(|| {
+            TracerError::invalid_data(path, "the vertex element has no x, y and z properties")
+        })?;
+        let normal = find(&[&["nx"], &["ny"], &["nz"]]);
+        let color = find(&[
+            &["red", "diffuse_red", "r"],
+            &["green", "diffuse_green", "g"],
+            &["blue", "diffuse_blue", "b"],
+        ]);
+        let texture = find(&[
+            &["u", "s", "texture_u", "texture_s"],
+            &["v", "t", "texture_v", "texture_t"],
+        ]);
+
+        Ok(Self {
+            position: indices(position).try_into().unwrap(),
+            normal: normal.map(|normal| indices(normal).try_into().unwrap()),
+            color: color.map(|color| {
+                [0, 1, 2].map(|channel| (color[channel].0, color[channel].1.maximum()))
+            }),
+            texture: texture.map(|texture| indices(texture).try_into().unwrap()),
+        })
+    }
+
+    fn attributes(&self) -> Attributes {
+        Attributes {
+            texture: self.texture.is_some(),
+            normal: self.normal.is_some(),
+            color: self.color.is_some(),
+        }
+    }
+
+    fn read(&self, values: &[f64], starts: &[usize], model: &mut MeshModel) {
+        let value = |index: usize| values[starts[index]];
+        let vector = |[x, y, z]: [usize; 3]| Vector3::new(value(x), value(y), value(z));
+
+        model.positions.push(vector(self.position));
+        if let Some(normal) = self.normal {
+            model.normals.push(vector(normal));
+        }
+        // Integer colors are stored in sRGB, floating point ones are taken as linear
+        if let Some(color) = self.color {
+            let [r, g, b] = color.map(|(index, maximum)| match maximum {
+                Some(maximum) => srgb_to_linear((value(index) / maximum).clamp(0.0, 1.0)),
+                None => value(index),
+            });
+            model.colors.push(Vector3::new(r, g, b));
+        }
+        if let Some([u, v]) = self.texture {
+            model.texture_coordinates.push([value(u), value(v)]);
+        }
+    }
+}
+
+// The element data after the header
+enum Body<'a> {
+    Ascii {
+        lines: Lines<'a>,
+        line_number: usize,
+    },
+    Binary {
+        bytes: &'a [u8],
+        position: usize,
+        big_endian: bool,
+    },
+}
+
+impl Body<'_> {
+    // Reads the next instance of the element into `values`, with the index of each
+    // property's first value in `starts`. Lists are stored as their count followed by the
+    // items. Returns the line number for ASCII data.
+    fn read(
+        &mut self,
+        element: &Element,
+        values: &mut Vec<f64>,
+        starts: &mut Vec<usize>,
+        path: &Path,
+    ) -> Result<Option<usize>, TracerError> {
+        values.clear();
+        starts.clear();
+
+        match self {
+            Body::Ascii { lines, line_number } => {
+                let line = loop {
+                    let line = lines.next().ok_or_else(|| {
+                        TracerError::invalid_data(
+                            path,
+                            format!("unexpected end of file in a {} element", element.name),
+                        )
+                    })?;
+                    *line_number += 1;
+                    if !line.trim().is_empty() {
+                        break line;
+                    }
+                };
+                let line_number = *line_number;
+
+                let mut tokens = line.split_whitespace();
+                let mut next = || {
+                    let token = tokens.next().ok_or_else(|| {
+                        TracerError::parse(path, line_number, "expected more values")
+                    })?;
+                    token.parse::<f64>().map_err(|_| {
+                        TracerError::parse(
+                            path,
+                            line_number,
+                            format!("`{}` is not a number", token),
+                        )
+                    })
+                };
+
+                for property in &element.properties {
+                    starts.push(values.len());
+                    match property.kind {
+                        PropertyType::Scalar(_) => values.push(next()?),
+                        PropertyType::List { .. } => {
+                            let count = next()?;
+                            if count < 0.0 || count.fract() != 0.0 {
+                                return Err(TracerError::parse(
+                                    path,
+                                    line_number,
+                                    format!("`{}` is not a list length", count),
+                                ));
+                            }
+                            values.push(count);
+                            for _ in 0..count as usize {
+                                values.push(next()?);
+                            }
+                        }
+                    }
+                }
+
+                Ok(Some(line_number))
+            }
+            Body::Binary {
+                bytes,
+                position,
+                big_endian,
+            } => {
+                let mut next = |scalar| {
+                    read_scalar(bytes, position, *big_endian, scalar).ok_or_else(|| {
+                        TracerError::invalid_data(
+                            path,
+                            format!("unexpected end of file in a {} element", element.name),
+                        )
+                    })
+                };
+
+                for property in &element.properties {
+                    starts.push(values.len());
+                    match property.kind {
+                        PropertyType::Scalar(scalar) => values.push(next(scalar)?),
+                        PropertyType::List { count, item } => {
+                            let count = next(count)?;
+                            values.push(count);
+                            for _ in 0..count.max(0.0) as usize {
+                                values.push(next(item)?);
+                            }
+                        }
+                    }
+                }
+
+                Ok(None)
+            }
+        }
+    }
+}
+
+fn read_scalar(
+    bytes: &[u8],
+    position: &mut usize,
+    big_endian: bool,
+    scalar: Scalar,
+) -> Option<f64> {
+    let value = match scalar {
+        Scalar::Int8 => i8::from_le_bytes(take(bytes, position, big_endian)?) as f64,
+        Scalar::Uint8 => u8::from_le_bytes(take(bytes, position, big_endian)?) as f64,
+        Scalar::Int16 => i16::from_le_bytes(take(bytes, position, big_endian)?) as f64,
+        Scalar::Uint16 => u16::from_le_bytes(take(bytes, position, big_endian)?) as f64,
+        Scalar::Int32 => i32::from_le_bytes(take(bytes, position, big_endian)?) as f64,
+        Scalar::Uint32 => u32::from_le_bytes(take(bytes, position, big_endian)?) as f64,
+        Scalar::Float32 => f32::from_le_bytes(take(bytes, position, big_endian)?) as f64,
+        Scalar::Float64 => f64::from_le_bytes(take(bytes, position, big_endian)?),
+    };
+    Some(value)
+}
+
+// The next N bytes in little endian order
+fn take<const N: usize>(bytes: &[u8], position: &mut usize, big_endian: bool) -> Option<[u8; N]> {
+    let mut array: [u8; N] = bytes.get(*position..*position + N)?.try_into().ok()?;
+    *position += N;
+    if big_endian {
+        array.reverse();
+    }
+    Some(array)
+}
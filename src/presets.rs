@@ -0,0 +1,172 @@
+use std::sync::Arc;
+
+use crate::{
+    camera::Camera,
+    material::{Dielectric, Lambertian, Light, Material, Metal},
+    quad::Quad,
+    ray::WorldObject,
+    scene::Scene,
+    sphere::Sphere,
+    util::{random_color, random_color_range, random_float, random_unit_float},
+    vec::Vector3,
+    world::World,
+};
+
+// The final scene of Ray Tracing in One Weekend
+pub fn random_spheres() -> Scene {
+    let camera = Camera::builder()
+        .width(800)
+        .look_from(Vector3::new(13.0, 2.0, 3.0))
+        .look_at(Vector3::new(0.0, 0.0, 0.0))
+        .vertical_fov(20.0)
+        .focus_distance(10.0)
+        .defocus_angle(0.0)
+        .samples_per_pixel(250)
+        .background(Vector3::new(0.7, 0.8, 1.0))
+        .build();
+
+    let mut objects: Vec<Arc<WorldObject>> = vec![];
+
+    let material_ground = Material::Lambertian(Lambertian::new(Vector3::new(0.5, 0.5, 0.5)));
+    objects.push(
+        WorldObject::Sphere(Sphere::new(
+            Vector3::new(0.0, -1000.0, 0.0),
+            1000.0,
+            material_ground,
+        ))
+        .into(),
+    );
+
+    let material_glass = Material::Dielectric(Dielectric::new(1.50));
+    objects.push(
+        WorldObject::Sphere(Sphere::new(
+            Vector3::new(0.0, 1.0, 0.0),
+            1.0,
+            material_glass,
+        ))
+        .into(),
+    );
+
+    let material_diffuse = Material::Lambertian(Lambertian::new(Vector3::new(0.4, 0.2, 0.1)));
+    objects.push(
+        WorldObject::Sphere(Sphere::new(
+            Vector3::new(-4.0, 1.0, 0.0),
+            1.0,
+            material_diffuse,
+        ))
+        .into(),
+    );
+
+    let material_metal = Material::Metal(Metal::new(Vector3::new(0.7, 0.6, 0.5), 0.0));
+    objects.push(
+        WorldObject::Sphere(Sphere::new(
+            Vector3::new(4.0, 1.0, 0.0),
+            1.0,
+            material_metal,
+        ))
+        .into(),
+    );
+
+    for x in -11..11 {
+        for z in -11..11 {
+            let random_material = random_unit_float();
+            let center = Vector3::new(
+                x as f64 + 0.9 * random_unit_float(),
+                0.2,
+                z as f64 + 0.9 * random_unit_float(),
+            );
+
+            if (center - Vector3::new(4.0, 0.2, 0.0)).length() > 0.9 {
+                let material = match random_material {
+                    _ if (0.0..=0.8).contains(&random_material) => {
+                        Material::Lambertian(Lambertian::new(random_color() * random_color()))
+                    }
+                    _ if (0.8..=0.95).contains(&random_material) => Material::Metal(Metal::new(
+                        random_color_range(0.5, 1.0),
+                        random_float(0.0, 0.5),
+                    )),
+                    _ => Material::Dielectric(Dielectric::new(1.50)),
+                };
+
+                objects.push(WorldObject::Sphere(Sphere::new(center, 0.2, material)).into());
+            }
+        }
+    }
+
+    Scene::new(camera, World::new(objects))
+}
+
+// Cornell box lit by a single area light in the ceiling
+pub fn cornell_box() -> Scene {
+    let camera = Camera::builder()
+        .width(600)
+        .aspect_ratio(1.0)
+        .look_from(Vector3::new(278.0, 278.0, -800.0))
+        .look_at(Vector3::new(278.0, 278.0, 0.0))
+        .vertical_fov(40.0)
+        .samples_per_pixel(200)
+        .background(Vector3::zero())
+        .build();
+
+    let red = Material::Lambertian(Lambertian::new(Vector3::new(0.65, 0.05, 0.05)));
+    let white = Material::Lambertian(Lambertian::new(Vector3::new(0.73, 0.73, 0.73)));
+    let green = Material::Lambertian(Lambertian::new(Vector3::new(0.12, 0.45, 0.15)));
+    let light = Material::Light(Light::new(Vector3::new(15.0, 15.0, 15.0)));
+
+    let quads = [
+        (
+            Vector3::new(555.0, 0.0, 0.0),
+            Vector3::new(0.0, 555.0, 0.0),
+            Vector3::new(0.0, 0.0, 555.0),
+            green,
+        ),
+        (
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 555.0, 0.0),
+            Vector3::new(0.0, 0.0, 555.0),
+            red,
+        ),
+        (
+            Vector3::new(343.0, 554.0, 332.0),
+            Vector3::new(-130.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, -105.0),
+            light,
+        ),
+        (
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(555.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, 555.0),
+            white.clone(),
+        ),
+        (
+            Vector3::new(555.0, 555.0, 555.0),
+            Vector3::new(-555.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, -555.0),
+            white.clone(),
+        ),
+        (
+            Vector3::new(0.0, 0.0, 555.0),
+            Vector3::new(555.0, 0.0, 0.0),
+            Vector3::new(0.0, 555.0, 0.0),
+            white.clone(),
+        ),
+    ];
+
+    let mut objects: Vec<Arc<WorldObject>> = quads
+        .into_iter()
+        .map(|(q, u, v, material)| Arc::new(WorldObject::Quad(Quad::new_quad(q, u, v, material))))
+        .collect();
+
+    objects.push(Arc::new(WorldObject::Sphere(Sphere::new(
+        Vector3::new(190.0, 90.0, 190.0),
+        90.0,
+        Material::Dielectric(Dielectric::new(1.5)),
+    ))));
+    objects.push(Arc::new(WorldObject::Sphere(Sphere::new(
+        Vector3::new(370.0, 120.0, 370.0),
+        120.0,
+        Material::Metal(Metal::new(Vector3::new(0.8, 0.85, 0.88), 0.05)),
+    ))));
+
+    Scene::new(camera, World::new(objects))
+}
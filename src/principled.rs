@@ -0,0 +1,307 @@
+use std::f64::consts::PI;
+
+use crate::{
+    bsdf::{BsdfFlags, BsdfSample, Bxdf, Scatterable},
+    material::{grey, RoughDielectric},
+    microfacet::{fresnel_dielectric, microfacet_normal, reflect, refract, TrowbridgeReitz},
+    ray::HitRecord,
+    texture::Texture,
+    tonemap::luminance,
+    util::random_unit_float,
+    vec::{dot_product, unit_vector, Vector3},
+};
+
+// The lobes are sampled against each other, which needs some spread in each of them, so
+// mirror-like surfaces stay very slightly rough
+const MIN_ROUGHNESS: f64 = 0.05;
+// Reflectance at normal incidence of a dielectric with a specular of 1, 0.5 gives the
+// 4% of common materials with a refraction index around 1.5
+const MAX_DIELECTRIC_REFLECTANCE: f64 = 0.08;
+// The clear coat is a thin lacquer of refraction index 1.5 that reflects at most a quarter
+// as much as the specular layer, as in Disney's model
+const CLEARCOAT_REFLECTANCE: f64 = 0.04;
+const CLEARCOAT_STRENGTH: f64 = 0.25;
+
+// Layered material after Disney's principled BSDF (Burley, "Physically Based Shading at
+// Disney", 2012): a diffuse base with sheen, a specular layer that blends into a metal with
+// `metallic`, rough glass taking over the base with `transmission` and a clear coat on top.
+// All parameters except the refraction index can be textured, single numbers are read from
+// the first channel of their texture.
+#[derive(Debug, Clone)]
+pub struct Principled {
+    base_color: Texture,
+    metallic: Texture,
+    roughness: Texture,
+    specular: Texture,
+    sheen: Texture,
+    clearcoat: Texture,
+    clearcoat_roughness: Texture,
+    transmission: Texture,
+    refraction_index: f64,
+}
+
+impl Principled {
+    pub fn new(base_color: Vector3) -> Self {
+        Self::from_texture(base_color.into())
+    }
+
+    pub fn from_texture(base_color: Texture) -> Self {
+        Self {
+            base_color,
+            metallic: 0.0.into(),
+            roughness: 0.5.into(),
+            specular: 0.5.into(),
+            sheen: 0.0.into(),
+            clearcoat: 0.0.into(),
+            clearcoat_roughness: 0.1.into(),
+            transmission: 0.0.into(),
+            refraction_index: 1.5,
+        }
+    }
+
+    pub fn metallic(mut self, metallic: Texture) -> Self {
+        self.metallic = metallic;
+        self
+    }
+
+    pub fn roughness(mut self, roughness: Texture) -> Self {
+        self.roughness = roughness;
+        self
+    }
+
+    pub fn specular(mut self, specular: Texture) -> Self {
+        self.specular = specular;
+        self
+    }
+
+    pub fn sheen(mut self, sheen: Texture) -> Self {
+        self.sheen = sheen;
+        self
+    }
+
+    pub fn clearcoat(mut self, clearcoat: Texture) -> Self {
+        self.clearcoat = clearcoat;
+        self
+    }
+
+    pub fn clearcoat_roughness(mut self, roughness: Texture) -> Self {
+        self.clearcoat_roughness = roughness;
+        self
+    }
+
+    pub fn transmission(mut self, transmission: Texture) -> Self {
+        self.transmission = transmission;
+        self
+    }
+
+    pub fn refraction_index(mut self, refraction_index: f64) -> Self {
+        self.refraction_index = refraction_index;
+        self
+    }
+
+    // Looks up the parameters at the hit. Paths inside a transmissive material leave it
+    // through plain rough glass, the other layers only make sense on the outside.
+    pub fn bxdf(&self, hit_record: &HitRecord) -> Bxdf {
+        let scalar = |texture: &Texture| {
+            texture
+                .value(hit_record.u(), hit_record.v(), hit_record.point())
+                .x()
+                .clamp(0.0, 1.0)
+        };
+
+        let roughness = scalar(&self.roughness).max(MIN_ROUGHNESS);
+        let transmission = scalar(&self.transmission);
+        if !hit_record.front_face() && transmission > 0.0 {
+            return RoughDielectric::new(self.refraction_index, roughness).bxdf(hit_record);
+        }
+
+        let base_color = self.base_color.value_at_hit(hit_record);
+        let metallic = scalar(&self.metallic);
+        let dielectric_reflectance = MAX_DIELECTRIC_REFLECTANCE * scalar(&self.specular);
+
+        Bxdf::Principled(PrincipledBxdf {
+            base_color,
+            metallic,
+            roughness,
+            sheen: scalar(&self.sheen),
+            clearcoat: scalar(&self.clearcoat),
+            transmission,
+            specular_color: grey(dielectric_reflectance * (1.0 - metallic)) + base_color * metallic,
+            distribution: TrowbridgeReitz::new(roughness),
+            clearcoat_distribution: TrowbridgeReitz::new(
+                scalar(&self.clearcoat_roughness).max(MIN_ROUGHNESS),
+            ),
+            eta: self.refraction_index,
+        })
+    }
+}
+
+// Parameters of a principled material at one hit
+#[derive(Debug, Clone, Copy)]
+pub struct PrincipledBxdf {
+    base_color: Vector3,
+    metallic: f64,
+    roughness: f64,
+    sheen: f64,
+    clearcoat: f64,
+    transmission: f64,
+    // Reflectance at normal incidence of the specular layer
+    specular_color: Vector3,
+    distribution: TrowbridgeReitz,
+    clearcoat_distribution: TrowbridgeReitz,
+    eta: f64,
+}
+
+impl PrincipledBxdf {
+    // Chances of sampling the diffuse, specular, transmission and clear coat lobes, by
+    // roughly how much light each of them scatters
+    fn weights(&self, wo: Vector3) -> [f64; 4] {
+        let dielectric = 1.0 - self.metallic;
+        let weights = [
+            dielectric * (1.0 - self.transmission) * (luminance(self.base_color) + self.sheen),
+            luminance(fresnel_schlick(self.specular_color, wo.z())),
+            dielectric * self.transmission * luminance(self.base_color),
+            CLEARCOAT_STRENGTH
+                * self.clearcoat
+                * luminance(fresnel_schlick(grey(CLEARCOAT_REFLECTANCE), wo.z())),
+        ];
+
+        let total: f64 = weights.iter().sum();
+        if total <= 0.0 {
+            return [0.0; 4];
+        }
+        weights.map(|weight| weight / total)
+    }
+
+    // Picks a lobe by its weight and samples a direction from it. Reflections that end up
+    // below the surface and refractions above it are dropped, pdf() doesn't count them.
+    fn sample_direction(&self, wo: Vector3) -> Option<(Vector3, BsdfFlags)> {
+        let [diffuse, specular, transmission, _] = self.weights(wo);
+        let choice = random_unit_float();
+
+        if choice < diffuse {
+            // The normal plus a random unit vector is cosine weighted, as for Lambertian
+            let direction = Vector3::new(0.0, 0.0, 1.0) + Vector3::random_unit_vector();
+            let flags = BsdfFlags::DIFFUSE | BsdfFlags::REFLECTION;
+            if direction.near_zero() {
+                return Some((Vector3::new(0.0, 0.0, 1.0), flags));
+            }
+            return Some((unit_vector(direction), flags));
+        }
+
+        let wi = if choice < diffuse + specular {
+            reflect(wo, self.distribution.sample_visible_normal(wo))
+        } else if choice < diffuse + specular + transmission {
+            let wi = refract(wo, self.distribution.sample_visible_normal(wo), self.eta)?;
+            return (wi.z() < 0.0).then_some((wi, BsdfFlags::GLOSSY | BsdfFlags::TRANSMISSION));
+        } else {
+            reflect(wo, self.clearcoat_distribution.sample_visible_normal(wo))
+        };
+        (wi.z() > 0.0).then_some((wi, BsdfFlags::GLOSSY | BsdfFlags::REFLECTION))
+    }
+}
+
+impl Scatterable for PrincipledBxdf {
+    // One lobe picks the direction, but all lobes that could have picked it weigh in
+    fn sample(&self, wo: Vector3) -> Option<BsdfSample> {
+        if wo.z() <= 0.0 {
+            return None;
+        }
+
+        let (wi, flags) = self.sample_direction(wo)?;
+        let pdf = self.pdf(wo, wi);
+        (pdf > 0.0).then(|| BsdfSample::new(wi, self.eval(wo, wi), pdf, flags))
+    }
+
+    // Summed over all lobes
+    fn eval(&self, wo: Vector3, wi: Vector3) -> Vector3 {
+        if wo.z() <= 0.0 || wi.z() == 0.0 {
+            return Vector3::zero();
+        }
+        let dielectric = 1.0 - self.metallic;
+
+        if wi.z() < 0.0 {
+            let Some(wm) = microfacet_normal(wo, wi, self.eta) else {
+                return Vector3::zero();
+            };
+            let fresnel = fresnel_dielectric(dot_product(wo, wm), self.eta);
+            let denominator = (dot_product(wi, wm) + dot_product(wo, wm) / self.eta).powi(2);
+            let refracted = self.distribution.d(wm)
+                * self.distribution.g(wo, wi)
+                * (1.0 - fresnel)
+                * (dot_product(wi, wm) * dot_product(wo, wm)).abs()
+                / (wo.z() * denominator * self.eta * self.eta);
+            return self.base_color * (dielectric * self.transmission * refracted);
+        }
+
+        let wm = unit_vector(wo + wi);
+        let cos_d = dot_product(wi, wm);
+
+        // Burley's diffuse, which rough surfaces brighten and smooth ones darken towards
+        // grazing angles, and a sheen that lights up the edges of cloth
+        let retroreflection = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+        let diffuse = (1.0 + (retroreflection - 1.0) * schlick_weight(wi.z()))
+            * (1.0 + (retroreflection - 1.0) * schlick_weight(wo.z()))
+            / PI;
+        let sheen = self.sheen * schlick_weight(cos_d);
+        let base = (self.base_color * diffuse + grey(sheen))
+            * (dielectric * (1.0 - self.transmission) * wi.z());
+
+        let specular = fresnel_schlick(self.specular_color, dot_product(wo, wm))
+            * (self.distribution.d(wm) * self.distribution.g(wo, wi) / (4.0 * wo.z()));
+
+        let clearcoat = CLEARCOAT_STRENGTH
+            * self.clearcoat
+            * fresnel_schlick(grey(CLEARCOAT_REFLECTANCE), dot_product(wo, wm)).x()
+            * self.clearcoat_distribution.d(wm)
+            * self.clearcoat_distribution.g(wo, wi)
+            / (4.0 * wo.z());
+
+        base + specular + grey(clearcoat)
+    }
+
+    // Density with which sample() picks the given direction, through any of the lobes
+    fn pdf(&self, wo: Vector3, wi: Vector3) -> f64 {
+        if wo.z() <= 0.0 || wi.z() == 0.0 {
+            return 0.0;
+        }
+        let [diffuse, specular, transmission, clearcoat] = self.weights(wo);
+
+        if wi.z() < 0.0 {
+            let Some(wm) = microfacet_normal(wo, wi, self.eta) else {
+                return 0.0;
+            };
+            let denominator = (dot_product(wi, wm) + dot_product(wo, wm) / self.eta).powi(2);
+            return transmission
+                * self.distribution.visible_pdf(wo, wm)
+                * dot_product(wi, wm).abs()
+                / denominator;
+        }
+
+        let wm = unit_vector(wo + wi);
+        let reflection = |distribution: &TrowbridgeReitz| {
+            distribution.visible_pdf(wo, wm) / (4.0 * dot_product(wo, wm))
+        };
+        diffuse * wi.z() / PI
+            + specular * reflection(&self.distribution)
+            + clearcoat * reflection(&self.clearcoat_distribution)
+    }
+
+    fn flags(&self) -> BsdfFlags {
+        let flags = BsdfFlags::DIFFUSE | BsdfFlags::GLOSSY | BsdfFlags::REFLECTION;
+        if self.transmission > 0.0 {
+            flags | BsdfFlags::TRANSMISSION
+        } else {
+            flags
+        }
+    }
+}
+
+// Schlick's approximation of the Fresnel reflectance
+fn fresnel_schlick(reflectance: Vector3, cos_theta: f64) -> Vector3 {
+    reflectance + (grey(1.0) - reflectance) * schlick_weight(cos_theta)
+}
+
+fn schlick_weight(cos_theta: f64) -> f64 {
+    (1.0 - cos_theta).clamp(0.0, 1.0).powi(5)
+}
@@ -1,16 +1,22 @@
+use std::sync::Arc;
+
 use crate::{
     aabb::AABB,
+    bvh::BVH,
     material::Material,
-    ray::{HitRecord, Hittable, Interval, Ray},
+    ray::{HitRecord, Hittable, Interval, Ray, Sampleable, WorldObject},
+    util::random_unit_float,
     vec::{cross_product, dot_product, unit_vector, Vector3},
 };
 
+#[derive(Debug, Clone, Copy)]
 pub enum QuadType {
     Quad,
     Triangle,
 }
 
 // Quadrilateral - technically a parallelogram
+#[derive(Clone)]
 pub struct Quad {
     q: Vector3,
     u: Vector3,
@@ -18,6 +24,7 @@ pub struct Quad {
     w: Vector3,
     normal: Vector3,
     d: f64,
+    area: f64,
     quad_type: QuadType,
     material: Material,
     bounding_box: AABB,
@@ -37,6 +44,10 @@ impl Quad {
         let normal = unit_vector(n);
         let d = dot_product(normal, q);
         let w = n / dot_product(n, n);
+        let area = match quad_type {
+            QuadType::Quad => n.length(),
+            QuadType::Triangle => n.length() / 2.0,
+        };
 
         let bounding_box_diagonal_1 = AABB::from_points(q, q + u + v);
         let bounding_box_diagonal_2 = AABB::from_points(q + u, q + v);
@@ -51,23 +62,49 @@ impl Quad {
             w,
             normal,
             d,
+            area,
             quad_type,
             material,
             bounding_box,
         }
     }
 
-    pub fn material(&self) -> Material {
-        self.material
+    pub fn material(&self) -> &Material {
+        &self.material
     }
 }
 
+// Axis-aligned box between two opposite corners, made of six quads facing outwards
+pub fn quad_box(a: Vector3, b: Vector3, material: Material) -> WorldObject {
+    let min = Vector3::new(a.x().min(b.x()), a.y().min(b.y()), a.z().min(b.z()));
+    let max = Vector3::new(a.x().max(b.x()), a.y().max(b.y()), a.z().max(b.z()));
+
+    let dx = Vector3::new(max.x() - min.x(), 0.0, 0.0);
+    let dy = Vector3::new(0.0, max.y() - min.y(), 0.0);
+    let dz = Vector3::new(0.0, 0.0, max.z() - min.z());
+
+    let sides = [
+        (Vector3::new(min.x(), min.y(), max.z()), dx, dy), // Front
+        (Vector3::new(max.x(), min.y(), max.z()), -dz, dy), // Right
+        (Vector3::new(max.x(), min.y(), min.z()), -dx, dy), // Back
+        (Vector3::new(min.x(), min.y(), min.z()), dz, dy), // Left
+        (Vector3::new(min.x(), max.y(), max.z()), dx, -dz), // Top
+        (Vector3::new(min.x(), min.y(), min.z()), dx, dz), // Bottom
+    ];
+    let quads = sides
+        .into_iter()
+        .map(|(q, u, v)| Arc::new(WorldObject::Quad(Quad::new_quad(q, u, v, material.clone()))))
+        .collect();
+
+    WorldObject::BVH(BVH::new(quads))
+}
+
 impl Hittable for Quad {
     fn bounding_box(&self) -> crate::aabb::AABB {
         self.bounding_box
     }
 
-    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<HitRecord<'_>> {
         let denominator = dot_product(self.normal, ray.direction());
 
         if denominator.abs() < 1e-8 {
@@ -108,12 +145,46 @@ impl Hittable for Quad {
             -self.normal
         };
 
+        // The planar coordinates double as texture coordinates, for triangles
+        // they are the barycentric weights of the second and third vertex
         Some(HitRecord::new(
             intersection,
             normal,
-            self.material,
+            &self.material,
             t,
             front_face,
+            alpha,
+            beta,
         ))
     }
 }
+
+impl Sampleable for Quad {
+    fn sample_direction(&self, origin: Vector3, _time: f64) -> Option<Vector3> {
+        let (mut alpha, mut beta) = (random_unit_float(), random_unit_float());
+
+        // Fold the far half of the parallelogram back onto the triangle
+        if let QuadType::Triangle = self.quad_type {
+            if alpha + beta > 1.0 {
+                alpha = 1.0 - alpha;
+                beta = 1.0 - beta;
+            }
+        }
+
+        let point = self.q + (self.u * alpha) + (self.v * beta);
+        Some(point - origin)
+    }
+
+    fn pdf_value(&self, origin: Vector3, direction: Vector3, _time: f64) -> f64 {
+        let ray = Ray::new(origin, direction);
+        let Some(hit) = self.hit(&ray, &Interval::new(0.001, f64::INFINITY)) else {
+            return 0.0;
+        };
+
+        // Convert the area density 1 / area to solid angle
+        let distance_squared = hit.t() * hit.t() * direction.length_squared();
+        let cosine = (dot_product(direction, self.normal) / direction.length()).abs();
+
+        distance_squared / (cosine * self.area)
+    }
+}
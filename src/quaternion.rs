@@ -0,0 +1,119 @@
+// Unit quaternion for blending rotations
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quaternion {
+    w: f64,
+    x: f64,
+    y: f64,
+    z: f64,
+}
+
+impl Quaternion {
+    // Rotation of a row-major orthonormal matrix with determinant 1, after Shepperd's method
+    // of starting from the largest component to stay accurate
+    pub fn from_rotation_matrix(m: [[f64; 3]; 3]) -> Self {
+        let trace = m[0][0] + m[1][1] + m[2][2];
+        let quaternion = if trace > 0.0 {
+            let s = 2.0 * (trace + 1.0).sqrt();
+            Self {
+                w: s / 4.0,
+                x: (m[2][1] - m[1][2]) / s,
+                y: (m[0][2] - m[2][0]) / s,
+                z: (m[1][0] - m[0][1]) / s,
+            }
+        } else if m[0][0] > m[1][1] && m[0][0] > m[2][2] {
+            let s = 2.0 * (1.0 + m[0][0] - m[1][1] - m[2][2]).sqrt();
+            Self {
+                w: (m[2][1] - m[1][2]) / s,
+                x: s / 4.0,
+                y: (m[0][1] + m[1][0]) / s,
+                z: (m[0][2] + m[2][0]) / s,
+            }
+        } else if m[1][1] > m[2][2] {
+            let s = 2.0 * (1.0 + m[1][1] - m[0][0] - m[2][2]).sqrt();
+            Self {
+                w: (m[0][2] - m[2][0]) / s,
+                x: (m[0][1] + m[1][0]) / s,
+                y: s / 4.0,
+                z: (m[1][2] + m[2][1]) / s,
+            }
+        } else {
+            let s = 2.0 * (1.0 + m[2][2] - m[0][0] - m[1][1]).sqrt();
+            Self {
+                w: (m[1][0] - m[0][1]) / s,
+                x: (m[0][2] + m[2][0]) / s,
+                y: (m[1][2] + m[2][1]) / s,
+                z: s / 4.0,
+            }
+        };
+        quaternion.normalized()
+    }
+
+    pub fn to_rotation_matrix(&self) -> [[f64; 3]; 3] {
+        let Self { w, x, y, z } = *self;
+        [
+            [
+                1.0 - 2.0 * (y * y + z * z),
+                2.0 * (x * y - w * z),
+                2.0 * (x * z + w * y),
+            ],
+            [
+                2.0 * (x * y + w * z),
+                1.0 - 2.0 * (x * x + z * z),
+                2.0 * (y * z - w * x),
+            ],
+            [
+                2.0 * (x * z - w * y),
+                2.0 * (y * z + w * x),
+                1.0 - 2.0 * (x * x + y * y),
+            ],
+        ]
+    }
+
+    pub fn dot(&self, other: &Quaternion) -> f64 {
+        self.w * other.w + self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    // Rotates at a constant rate along the shorter way from this rotation to the other one
+    pub fn slerp(&self, other: &Quaternion, t: f64) -> Self {
+        // q and -q are the same rotation, the one closer to self takes the shorter way
+        let mut cos_theta = self.dot(other);
+        let other = if cos_theta < 0.0 {
+            cos_theta = -cos_theta;
+            other.scaled(-1.0)
+        } else {
+            *other
+        };
+
+        // Nearly equal rotations would divide by almost zero, blending them linearly is as good
+        if cos_theta > 0.9995 {
+            return self.scaled(1.0 - t).plus(&other.scaled(t)).normalized();
+        }
+
+        let theta = cos_theta.acos();
+        let sin_theta = theta.sin();
+        self.scaled(((1.0 - t) * theta).sin() / sin_theta)
+            .plus(&other.scaled((t * theta).sin() / sin_theta))
+    }
+
+    fn scaled(&self, factor: f64) -> Self {
+        Self {
+            w: self.w * factor,
+            x: self.x * factor,
+            y: self.y * factor,
+            z: self.z * factor,
+        }
+    }
+
+    fn plus(&self, other: &Quaternion) -> Self {
+        Self {
+            w: self.w + other.w,
+            x: self.x + other.x,
+            y: self.y + other.y,
+            z: self.z + other.z,
+        }
+    }
+
+    fn normalized(&self) -> Self {
+        self.scaled(1.0 / self.dot(self).sqrt())
+    }
+}
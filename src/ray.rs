@@ -1,16 +1,22 @@
+use std::sync::Arc;
+
 use crate::{
     aabb::AABB,
-    bvh::BVHNode,
+    bvh::BVH,
+    instance::Instance,
     material::Material,
+    medium::{ConstantMedium, HeterogeneousMedium},
     mesh::Mesh,
     quad::Quad,
     sphere::Sphere,
-    vec::{dot_product, Vector3},
+    vec::Vector3,
 };
 
 pub struct Ray {
     origin: Vector3,    // A
     direction: Vector3, // b
+    // Moment within the shutter interval the ray samples, for motion blur
+    time: f64,
 
     // Cached values
     direction_inverse: Vector3,
@@ -19,11 +25,25 @@ pub struct Ray {
 
 impl Ray {
     pub fn new(origin: Vector3, direction: Vector3) -> Ray {
+        Self::with_time(origin, direction, 0.0)
+    }
+
+    pub fn with_time(origin: Vector3, direction: Vector3, time: f64) -> Ray {
         // Precompute direction inverse for box intersection.
-        let direction_inverse = Vector3::new(1.0 / direction.x(), 1.0 / direction.y(), 1.0 / direction.z());
+        let direction_inverse = Vector3::new(
+            1.0 / direction.x(),
+            1.0 / direction.y(),
+            1.0 / direction.z(),
+        );
         // Precompute direction length squared.
         let direction_length_squared = direction.length_squared();
-        Self { origin, direction, direction_inverse, direction_length_squared }
+        Self {
+            origin,
+            direction,
+            time,
+            direction_inverse,
+            direction_length_squared,
+        }
     }
 
     pub fn at(&self, t: f64) -> Vector3 {
@@ -38,6 +58,10 @@ impl Ray {
         self.direction
     }
 
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
     pub fn direction_inverse(&self) -> Vector3 {
         self.direction_inverse
     }
@@ -47,31 +71,46 @@ impl Ray {
     }
 }
 
-pub struct HitRecord {
+pub struct HitRecord<'a> {
     point: Vector3,
     normal: Vector3,
-    material: Material, // TODO: Should this be a borrowed value?
+    material: &'a Material,
     t: f64,
     front_face: bool,
+    // Surface coordinates for texture lookups
+    u: f64,
+    v: f64,
+    // Interpolated color of meshes with vertex colors, multiplies the albedo
+    vertex_color: Option<Vector3>,
 }
 
-impl HitRecord {
+impl<'a> HitRecord<'a> {
     pub fn new(
         point: Vector3,
         normal: Vector3,
-        material: Material,
+        material: &'a Material,
         t: f64,
         front_face: bool,
-    ) -> HitRecord {
+        u: f64,
+        v: f64,
+    ) -> HitRecord<'a> {
         Self {
             point,
             normal,
             material,
             t,
             front_face,
+            u,
+            v,
+            vertex_color: None,
         }
     }
 
+    pub fn with_vertex_color(mut self, color: Option<Vector3>) -> Self {
+        self.vertex_color = color;
+        self
+    }
+
     pub fn point(&self) -> Vector3 {
         self.point
     }
@@ -80,7 +119,7 @@ impl HitRecord {
         self.normal
     }
 
-    pub fn material(&self) -> Material {
+    pub fn material(&self) -> &'a Material {
         self.material
     }
 
@@ -91,39 +130,127 @@ impl HitRecord {
     pub fn front_face(&self) -> bool {
         self.front_face
     }
+
+    pub fn u(&self) -> f64 {
+        self.u
+    }
+
+    pub fn v(&self) -> f64 {
+        self.v
+    }
+
+    pub fn vertex_color(&self) -> Option<Vector3> {
+        self.vertex_color
+    }
 }
 
 // TODO: Rename
 pub enum WorldObject {
-    BVHNode(BVHNode),
+    BVH(BVH),
     Sphere(Sphere),
     Quad(Quad),
     Mesh(Mesh),
+    Instance(Instance),
+    ConstantMedium(ConstantMedium),
+    HeterogeneousMedium(HeterogeneousMedium),
 }
 
 impl WorldObject {
-    pub fn hit(&self, ray: &Ray, t: &Interval) -> Option<HitRecord> {
+    // Primitives with a Light material that support direct light sampling. The emissive
+    // triangles of meshes become lights of their own, and instances place the lights of
+    // their object the same way they place the object.
+    pub fn lights(self: &Arc<Self>) -> Vec<Arc<WorldObject>> {
+        match self.as_ref() {
+            WorldObject::Sphere(sphere) if sphere.material().is_emissive() => vec![self.clone()],
+            WorldObject::Quad(quad) if quad.material().is_emissive() => vec![self.clone()],
+            WorldObject::Mesh(mesh) => mesh
+                .lights()
+                .into_iter()
+                .map(|triangle| Arc::new(WorldObject::Quad(triangle)))
+                .collect(),
+            WorldObject::Instance(instance) => instance
+                .object()
+                .lights()
+                .into_iter()
+                .map(|light| Arc::new(WorldObject::Instance(instance.with_object(light))))
+                .collect(),
+            WorldObject::BVH(bvh) => bvh
+                .primitives()
+                .iter()
+                .flat_map(|object| object.lights())
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    pub fn sample_direction(&self, origin: Vector3, time: f64) -> Option<Vector3> {
+        match self {
+            WorldObject::Sphere(sphere) => sphere.sample_direction(origin, time),
+            WorldObject::Quad(quad) => quad.sample_direction(origin, time),
+            WorldObject::Instance(instance) => instance.sample_direction(origin, time),
+            _ => None,
+        }
+    }
+
+    pub fn pdf_value(&self, origin: Vector3, direction: Vector3, time: f64) -> f64 {
         match self {
-            WorldObject::BVHNode(node) => node.hit(ray, t),
+            WorldObject::Sphere(sphere) => sphere.pdf_value(origin, direction, time),
+            WorldObject::Quad(quad) => quad.pdf_value(origin, direction, time),
+            WorldObject::Instance(instance) => instance.pdf_value(origin, direction, time),
+            _ => 0.0,
+        }
+    }
+}
+
+pub trait Hittable {
+    fn hit(&self, ray: &Ray, t: &Interval) -> Option<HitRecord<'_>>;
+    fn bounding_box(&self) -> AABB;
+}
+
+impl Hittable for WorldObject {
+    fn hit(&self, ray: &Ray, t: &Interval) -> Option<HitRecord<'_>> {
+        match self {
+            WorldObject::BVH(bvh) => bvh.hit(ray, t),
             WorldObject::Sphere(sphere) => sphere.hit(ray, t),
             WorldObject::Quad(quad) => quad.hit(ray, t),
             WorldObject::Mesh(mesh) => mesh.hit(ray, t),
+            WorldObject::Instance(instance) => instance.hit(ray, t),
+            WorldObject::ConstantMedium(medium) => medium.hit(ray, t),
+            WorldObject::HeterogeneousMedium(medium) => medium.hit(ray, t),
         }
     }
 
-    pub fn bounding_box(&self) -> AABB {
+    fn bounding_box(&self) -> AABB {
         match self {
-            WorldObject::BVHNode(node) => node.bounding_box(),
+            WorldObject::BVH(bvh) => bvh.bounding_box(),
             WorldObject::Sphere(sphere) => sphere.bounding_box(),
             WorldObject::Quad(quad) => quad.bounding_box(),
             WorldObject::Mesh(mesh) => mesh.bounding_box(),
+            WorldObject::Instance(instance) => instance.bounding_box(),
+            WorldObject::ConstantMedium(medium) => medium.bounding_box(),
+            WorldObject::HeterogeneousMedium(medium) => medium.bounding_box(),
         }
     }
 }
 
-pub trait Hittable {
-    fn hit(&self, ray: &Ray, t: &Interval) -> Option<HitRecord>;
-    fn bounding_box(&self) -> AABB;
+// Lets shared objects be stored in a BVH
+impl<T: Hittable + ?Sized> Hittable for Arc<T> {
+    fn hit(&self, ray: &Ray, t: &Interval) -> Option<HitRecord<'_>> {
+        (**self).hit(ray, t)
+    }
+
+    fn bounding_box(&self) -> AABB {
+        (**self).bounding_box()
+    }
+}
+
+// Shapes that can be sampled by solid angle, so they can act as lights
+pub trait Sampleable {
+    // Direction from the origin towards a random point on the shape at the given time,
+    // None if the shape can't be sampled from there
+    fn sample_direction(&self, origin: Vector3, time: f64) -> Option<Vector3>;
+    // Solid angle density of sampling the given direction from the origin
+    fn pdf_value(&self, origin: Vector3, direction: Vector3, time: f64) -> f64;
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -0,0 +1,928 @@
+use std::{
+    collections::HashMap,
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use serde::{de::DeserializeOwned, Deserialize};
+use toml::{Spanned, Value};
+
+use crate::{
+    aabb::AABB,
+    camera::Camera,
+    environment::{Environment, EnvironmentMap},
+    error::TracerError,
+    gltf_import::load_gltf,
+    instance::{Instance, Transform},
+    material::{
+        Conductor, Dielectric, Isotropic, Lambertian, Light, Material, Metal, RoughDielectric,
+    },
+    matrix::Matrix4,
+    medium::{ConstantMedium, DensityField, HeterogeneousMedium, NoiseField, VoxelGrid},
+    mesh::{Mesh, MeshOptions},
+    principled::Principled,
+    quad::{quad_box, Quad},
+    ray::{Hittable, WorldObject},
+    sky::Sky,
+    sphere::Sphere,
+    texture::{Checker, ImageTexture, NoiseTexture, Texture},
+    vec::Vector3,
+    world::World,
+};
+
+// Path, material override, crease angle bits and whether degenerate triangles are skipped
+type MeshKey = (PathBuf, Option<String>, Option<u64>, bool);
+
+// A camera and world loaded from a TOML scene description or a glTF file
+pub struct Scene {
+    camera: Camera,
+    world: World,
+}
+
+impl Scene {
+    pub fn new(camera: Camera, world: World) -> Self {
+        Self { camera, world }
+    }
+
+    // Loads a TOML scene, or a glTF scene for .gltf and .glb files
+    pub fn from_file(path: &Path) -> Result<Self, TracerError> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        if matches!(extension.as_deref(), Some("gltf" | "glb")) {
+            return Self::from_gltf(path);
+        }
+
+        let source = fs::read_to_string(path).map_err(|e| TracerError::io(path, e))?;
+        // Mesh paths are resolved relative to the scene file
+        let base_directory = path.parent().unwrap_or(Path::new("."));
+        Self::from_source(&source, base_directory).map_err(|e| e.in_file(path))
+    }
+
+    // Meshes, materials and the first camera of a glTF 2.0 file's default scene
+    pub fn from_gltf(path: &Path) -> Result<Self, TracerError> {
+        load_gltf(path)
+    }
+
+    pub fn from_source(source: &str, base_directory: &Path) -> Result<Self, TracerError> {
+        let description: SceneDescription = toml::from_str(source)
+            .map_err(|e| parse_error(source, e.span().unwrap_or(0..0), e.message().to_string()))?;
+
+        let mut textures = TextureLibrary {
+            source,
+            base_directory,
+            descriptions: &description.textures,
+            resolved: HashMap::new(),
+        };
+
+        let mut materials = HashMap::new();
+        for (name, entry) in &description.materials {
+            let material: MaterialDescription = deserialize_entry(source, entry)?;
+            let material = material
+                .build(&mut textures)
+                .map_err(|message| parse_error(source, entry.span(), message))?;
+            materials.insert(name.clone(), material);
+        }
+
+        // Meshes referenced more than once are loaded once and shared between their instances
+        let mut meshes: HashMap<MeshKey, Arc<WorldObject>> = HashMap::new();
+
+        let mut objects: Vec<Arc<WorldObject>> = vec![];
+        for entry in &description.objects {
+            let error = |message: String| parse_error(source, entry.span(), message);
+
+            // The transforms are shared by all object types, so they are split off before
+            // the object itself is deserialized
+            let mut table = entry.get_ref().clone();
+            let mut take_transform = |key: &str| {
+                table
+                    .as_table_mut()
+                    .and_then(|table| table.remove(key))
+                    .map(|transform| {
+                        TransformDescription::deserialize(transform)
+                            .map_err(|e| error(e.message().to_string()))?
+                            .build()
+                            .ok_or_else(|| error(format!("{} is not invertible", key)))
+                    })
+                    .transpose()
+            };
+            let transform = take_transform("transform")?;
+            // Where a moving object is at time 1
+            let end_transform = take_transform("end_transform")?;
+
+            let object = ObjectDescription::deserialize(table)
+                .map_err(|e| error(e.message().to_string()))?;
+
+            let world_object = match &object {
+                ObjectDescription::Mesh {
+                    path,
+                    material,
+                    crease_angle,
+                    skip_degenerate_triangles,
+                } => {
+                    let key = (
+                        base_directory.join(path),
+                        material.clone(),
+                        crease_angle.map(f64::to_bits),
+                        *skip_degenerate_triangles,
+                    );
+                    match meshes.get(&key) {
+                        Some(mesh) => mesh.clone(),
+                        None => {
+                            let mesh =
+                                Arc::new(object.build(&materials, base_directory).map_err(error)?);
+                            meshes.insert(key, mesh.clone());
+                            mesh
+                        }
+                    }
+                }
+                _ => Arc::new(object.build(&materials, base_directory).map_err(error)?),
+            };
+
+            objects.push(match (transform, end_transform) {
+                (start, Some(end)) => Arc::new(WorldObject::Instance(Instance::new_moving(
+                    world_object,
+                    start.unwrap_or_else(Transform::identity),
+                    end,
+                ))),
+                (Some(transform), None) => Arc::new(WorldObject::Instance(Instance::new(
+                    world_object,
+                    transform,
+                ))),
+                (None, None) => world_object,
+            });
+        }
+
+        if objects.is_empty() {
+            return Err(parse_error(
+                source,
+                0..0,
+                "scene does not contain any objects".to_string(),
+            ));
+        }
+
+        if let Some(fog) = &description.fog {
+            let fog_object = fog
+                .get_ref()
+                .build(vector(description.camera.get_ref().look_from), &objects)
+                .map_err(|message| parse_error(source, fog.span(), message))?;
+            objects.push(Arc::new(fog_object));
+        }
+
+        let camera = description
+            .camera
+            .get_ref()
+            .build()
+            .map_err(|message| parse_error(source, description.camera.span(), message))?;
+        let mut world = World::new(objects);
+        match (&description.environment, &description.sky) {
+            (Some(_), Some(_)) => {
+                return Err(parse_error(
+                    source,
+                    0..0,
+                    "scene has both an environment and a sky, only one can be used".to_string(),
+                ))
+            }
+            (Some(environment), None) => {
+                world =
+                    world.with_environment(Environment::Map(environment.build(base_directory)?));
+            }
+            (None, Some(sky)) => world = world.with_environment(Environment::Sky(sky.build())),
+            (None, None) => {}
+        }
+
+        Ok(Self { camera, world })
+    }
+
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    pub fn into_parts(self) -> (Camera, World) {
+        (self.camera, self.world)
+    }
+}
+
+// Points at the line of the scene source the span starts on
+fn parse_error(source: &str, span: Range<usize>, message: String) -> TracerError {
+    TracerError::Parse {
+        path: None,
+        line: line_number(source, span.start),
+        message,
+    }
+}
+
+fn line_number(source: &str, offset: usize) -> usize {
+    let offset = offset.min(source.len());
+    source[..offset].matches('\n').count() + 1
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDescription {
+    camera: Spanned<CameraDescription>,
+    #[serde(default)]
+    textures: HashMap<String, Spanned<Value>>,
+    #[serde(default)]
+    materials: HashMap<String, Spanned<Value>>,
+    #[serde(default)]
+    objects: Vec<Spanned<Value>>,
+    fog: Option<Spanned<FogDescription>>,
+    environment: Option<EnvironmentDescription>,
+    sky: Option<SkyDescription>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TransformDescription {
+    // Row-major matrix applied before the other components
+    matrix: Option<[[f64; 4]; 4]>,
+    scale: Option<ScaleDescription>,
+    // Degrees around the x, y and z axes, applied in that order
+    rotate: Option<[f64; 3]>,
+    translate: Option<[f64; 3]>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ScaleDescription {
+    Uniform(f64),
+    Axes([f64; 3]),
+}
+
+impl TransformDescription {
+    fn build(&self) -> Option<Transform> {
+        let mut transform = match self.matrix {
+            Some(matrix) => Transform::new(Matrix4::new(matrix))?,
+            None => Transform::identity(),
+        };
+
+        if let Some(scale) = &self.scale {
+            let scale = match *scale {
+                ScaleDescription::Uniform(scale) => Vector3::new(scale, scale, scale),
+                ScaleDescription::Axes(axes) => vector(axes),
+            };
+            transform = transform.then(Transform::new(Matrix4::scaling(scale))?);
+        }
+        if let Some([x, y, z]) = self.rotate {
+            transform = transform.rotate_x(x).rotate_y(y).rotate_z(z);
+        }
+        if let Some(translate) = self.translate {
+            transform = transform.translate(vector(translate));
+        }
+
+        Some(transform)
+    }
+}
+
+// Tagged entries are deserialized one at a time, so that errors inside them
+// can still be attributed to the table they came from.
+fn deserialize_entry<T: DeserializeOwned>(
+    source: &str,
+    entry: &Spanned<Value>,
+) -> Result<T, TracerError> {
+    T::deserialize(entry.get_ref().clone())
+        .map_err(|e| parse_error(source, entry.span(), e.message().to_string()))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDescription {
+    width: u32,
+    height: Option<u32>,
+    aspect_ratio: Option<f64>,
+    look_from: [f64; 3],
+    look_at: [f64; 3],
+    up: Option<[f64; 3]>,
+    vertical_fov: f64,
+    focus_distance: f64,
+    #[serde(default)]
+    defocus_angle: f64,
+    // Fraction of the motion from time 0 to 1 the shutter is open for
+    #[serde(default)]
+    shutter_open: f64,
+    #[serde(default = "default_shutter_close")]
+    shutter_close: f64,
+    samples_per_pixel: u32,
+    max_depth: Option<u32>,
+    #[serde(default)]
+    background: [f64; 3],
+}
+
+fn default_shutter_close() -> f64 {
+    1.0
+}
+
+impl CameraDescription {
+    fn build(&self) -> Result<Camera, String> {
+        if self.samples_per_pixel == 0 {
+            return Err("samples_per_pixel must be at least 1".to_string());
+        }
+
+        let mut builder = Camera::builder()
+            .width(self.width)
+            .look_from(vector(self.look_from))
+            .look_at(vector(self.look_at))
+            .vertical_fov(self.vertical_fov)
+            .focus_distance(self.focus_distance)
+            .defocus_angle(self.defocus_angle)
+            .shutter(self.shutter_open, self.shutter_close)
+            .samples_per_pixel(self.samples_per_pixel)
+            .background(vector(self.background));
+
+        if let Some(height) = self.height {
+            builder = builder.height(height);
+        }
+        if let Some(aspect_ratio) = self.aspect_ratio {
+            builder = builder.aspect_ratio(aspect_ratio);
+        }
+        if let Some(up) = self.up {
+            builder = builder.up(vector(up));
+        }
+        if let Some(max_depth) = self.max_depth {
+            builder = builder.max_depth(max_depth);
+        }
+
+        Ok(builder.build())
+    }
+}
+
+// Equirectangular image lighting the scene in place of the background color
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EnvironmentDescription {
+    path: PathBuf,
+    // Degrees around the y axis
+    #[serde(default)]
+    rotation: f64,
+    #[serde(default = "default_intensity")]
+    intensity: f64,
+}
+
+fn default_intensity() -> f64 {
+    1.0
+}
+
+impl EnvironmentDescription {
+    fn build(&self, base_directory: &Path) -> Result<EnvironmentMap, TracerError> {
+        let environment = EnvironmentMap::from_file(&base_directory.join(&self.path))?;
+        Ok(environment
+            .rotation(self.rotation)
+            .intensity(self.intensity))
+    }
+}
+
+// Analytic daylight in place of the background color
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SkyDescription {
+    // Points towards the sun
+    sun_direction: [f64; 3],
+    #[serde(default = "default_turbidity")]
+    turbidity: f64,
+    #[serde(default = "default_ground_albedo")]
+    ground_albedo: [f64; 3],
+    #[serde(default = "default_intensity")]
+    intensity: f64,
+}
+
+fn default_turbidity() -> f64 {
+    3.0
+}
+
+fn default_ground_albedo() -> [f64; 3] {
+    [0.3, 0.3, 0.3]
+}
+
+impl SkyDescription {
+    fn build(&self) -> Sky {
+        Sky::new(
+            vector(self.sun_direction),
+            self.turbidity,
+            vector(self.ground_albedo),
+        )
+        .intensity(self.intensity)
+    }
+}
+
+// Homogeneous medium filling a sphere around the camera
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FogDescription {
+    density: f64,
+    #[serde(default = "white")]
+    color: [f64; 3],
+    // Defaults to reaching the farthest corner of the scene's bounding box
+    radius: Option<f64>,
+}
+
+impl FogDescription {
+    fn build(&self, center: Vector3, objects: &[Arc<WorldObject>]) -> Result<WorldObject, String> {
+        if self.density <= 0.0 {
+            return Err(format!(
+                "fog density must be positive, got {}",
+                self.density
+            ));
+        }
+
+        let radius = self.radius.unwrap_or_else(|| {
+            let bounding_box = objects.iter().fold(AABB::empty(), |bounding_box, object| {
+                AABB::from_bounding_boxes(bounding_box, object.bounding_box())
+            });
+            let [x, y, z] = [0, 1, 2].map(|axis| {
+                let interval = bounding_box.axis_interval(axis);
+                let center = center.axis(axis);
+                (interval.min() - center)
+                    .abs()
+                    .max((interval.max() - center).abs())
+            });
+            Vector3::new(x, y, z).length()
+        });
+
+        let phase_function = Isotropic::new(vector(self.color));
+        let boundary = Sphere::new(center, radius, Material::Isotropic(phase_function.clone()));
+        Ok(WorldObject::ConstantMedium(ConstantMedium::new(
+            Arc::new(WorldObject::Sphere(boundary)),
+            self.density,
+            phase_function,
+        )))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum MaterialDescription {
+    Lambertian {
+        albedo: ColorOrTexture,
+    },
+    Metal {
+        albedo: ColorOrTexture,
+        fuzz: f64,
+    },
+    Dielectric {
+        refraction_index: f64,
+        roughness: Option<f64>,
+    },
+    // Rough metal, either a preset or a complex refraction index per color channel
+    Conductor {
+        preset: Option<ConductorPreset>,
+        eta: Option<[f64; 3]>,
+        k: Option<[f64; 3]>,
+        #[serde(default)]
+        roughness: f64,
+    },
+    Light {
+        color: [f64; 3],
+    },
+    // Layered material with optionally textured parameters
+    Principled {
+        base_color: ColorOrTexture,
+        metallic: Option<ScalarOrTexture>,
+        roughness: Option<ScalarOrTexture>,
+        specular: Option<ScalarOrTexture>,
+        sheen: Option<ScalarOrTexture>,
+        clearcoat: Option<ScalarOrTexture>,
+        clearcoat_roughness: Option<ScalarOrTexture>,
+        transmission: Option<ScalarOrTexture>,
+        refraction_index: Option<f64>,
+    },
+    // Phase function of media
+    Isotropic {
+        albedo: ColorOrTexture,
+        #[serde(default)]
+        emission: [f64; 3],
+    },
+}
+
+impl MaterialDescription {
+    fn build(&self, textures: &mut TextureLibrary) -> Result<Material, String> {
+        let material = match self {
+            MaterialDescription::Lambertian { albedo } => {
+                Material::Lambertian(Lambertian::from_texture(textures.get(albedo)?))
+            }
+            MaterialDescription::Metal { albedo, fuzz } => {
+                Material::Metal(Metal::from_texture(textures.get(albedo)?, *fuzz))
+            }
+            MaterialDescription::Dielectric {
+                refraction_index,
+                roughness: None,
+            } => Material::Dielectric(Dielectric::new(*refraction_index)),
+            MaterialDescription::Dielectric {
+                refraction_index,
+                roughness: Some(roughness),
+            } => Material::RoughDielectric(RoughDielectric::new(*refraction_index, *roughness)),
+            MaterialDescription::Conductor {
+                preset,
+                eta,
+                k,
+                roughness,
+            } => {
+                let conductor = match (preset, eta, k) {
+                    (Some(ConductorPreset::Gold), None, None) => Conductor::gold(*roughness),
+                    (Some(ConductorPreset::Copper), None, None) => Conductor::copper(*roughness),
+                    (Some(ConductorPreset::Aluminium), None, None) => {
+                        Conductor::aluminium(*roughness)
+                    }
+                    (None, Some(eta), Some(k)) => {
+                        Conductor::new(vector(*eta), vector(*k), *roughness)
+                    }
+                    _ => {
+                        return Err("conductor needs either a preset or both eta and k".to_string())
+                    }
+                };
+                Material::Conductor(conductor)
+            }
+            MaterialDescription::Principled {
+                base_color,
+                metallic,
+                roughness,
+                specular,
+                sheen,
+                clearcoat,
+                clearcoat_roughness,
+                transmission,
+                refraction_index,
+            } => {
+                let mut principled = Principled::from_texture(textures.get(base_color)?);
+                let parameters: [(_, PrincipledSetter); 7] = [
+                    (metallic, Principled::metallic),
+                    (roughness, Principled::roughness),
+                    (specular, Principled::specular),
+                    (sheen, Principled::sheen),
+                    (clearcoat, Principled::clearcoat),
+                    (clearcoat_roughness, Principled::clearcoat_roughness),
+                    (transmission, Principled::transmission),
+                ];
+                for (value, set) in parameters {
+                    if let Some(value) = value {
+                        principled = set(principled, textures.get_scalar(value)?);
+                    }
+                }
+                if let Some(refraction_index) = refraction_index {
+                    principled = principled.refraction_index(*refraction_index);
+                }
+                Material::Principled(Box::new(principled))
+            }
+            MaterialDescription::Light { color } => Material::Light(Light::new(vector(*color))),
+            MaterialDescription::Isotropic { albedo, emission } => Material::Isotropic(
+                Isotropic::from_texture(textures.get(albedo)?).emission(vector(*emission)),
+            ),
+        };
+
+        Ok(material)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ConductorPreset {
+    Gold,
+    Copper,
+    #[serde(alias = "aluminum")]
+    Aluminium,
+}
+
+// Colors can be given inline or by naming an entry in [textures]
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColorOrTexture {
+    Color([f64; 3]),
+    Texture(String),
+}
+
+// Builder method of a principled material parameter
+type PrincipledSetter = fn(Principled, Texture) -> Principled;
+
+// Parameters that are a single number, inline or as the first channel of a texture
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ScalarOrTexture {
+    Value(f64),
+    Texture(String),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum TextureDescription {
+    Solid {
+        color: [f64; 3],
+    },
+    Checker {
+        scale: f64,
+        even: ColorOrTexture,
+        odd: ColorOrTexture,
+    },
+    Image {
+        path: PathBuf,
+        // Data such as roughness maps is stored without the sRGB curve
+        #[serde(default)]
+        linear: bool,
+    },
+    Noise {
+        scale: f64,
+        #[serde(default = "white")]
+        color: [f64; 3],
+    },
+}
+
+fn white() -> [f64; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+// Builds named textures on first use, since textures can reference each other
+struct TextureLibrary<'a> {
+    source: &'a str,
+    base_directory: &'a Path,
+    descriptions: &'a HashMap<String, Spanned<Value>>,
+    // None marks a texture that is still being built, to catch cycles
+    resolved: HashMap<String, Option<Texture>>,
+}
+
+impl TextureLibrary<'_> {
+    fn get(&mut self, color: &ColorOrTexture) -> Result<Texture, String> {
+        let name = match color {
+            ColorOrTexture::Color(color) => return Ok(vector(*color).into()),
+            ColorOrTexture::Texture(name) => name,
+        };
+
+        match self.resolved.get(name) {
+            Some(Some(texture)) => return Ok(texture.clone()),
+            Some(None) => return Err(format!("texture `{}` references itself", name)),
+            None => {}
+        }
+
+        let entry = self
+            .descriptions
+            .get(name)
+            .ok_or_else(|| format!("unknown texture `{}`", name))?;
+
+        self.resolved.insert(name.clone(), None);
+        let texture = self.build(entry).map_err(|message| {
+            // Errors inside the texture are reported at the texture, not at its user
+            let line = line_number(self.source, entry.span().start);
+            format!("in texture `{}` (line {}): {}", name, line, message)
+        })?;
+        self.resolved.insert(name.clone(), Some(texture.clone()));
+
+        Ok(texture)
+    }
+
+    fn get_scalar(&mut self, value: &ScalarOrTexture) -> Result<Texture, String> {
+        match value {
+            ScalarOrTexture::Value(value) => Ok((*value).into()),
+            ScalarOrTexture::Texture(name) => self.get(&ColorOrTexture::Texture(name.clone())),
+        }
+    }
+
+    fn build(&mut self, entry: &Spanned<Value>) -> Result<Texture, String> {
+        let description = TextureDescription::deserialize(entry.get_ref().clone())
+            .map_err(|e| e.message().to_string())?;
+
+        let texture = match description {
+            TextureDescription::Solid { color } => vector(color).into(),
+            TextureDescription::Checker { scale, even, odd } => {
+                Texture::Checker(Checker::new(scale, self.get(&even)?, self.get(&odd)?))
+            }
+            TextureDescription::Image { path, linear } => {
+                let path = self.base_directory.join(path);
+                let image = if linear {
+                    ImageTexture::from_linear_file(&path)
+                } else {
+                    ImageTexture::from_file(&path)
+                };
+                let image =
+                    image.map_err(|e| format!("failed to load `{}`: {}", path.display(), e))?;
+                Texture::Image(image)
+            }
+            TextureDescription::Noise { scale, color } => {
+                Texture::Noise(NoiseTexture::new(scale, vector(color)))
+            }
+        };
+
+        Ok(texture)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum ObjectDescription {
+    Sphere {
+        center: [f64; 3],
+        // Where a moving sphere's center is at time 1
+        end_center: Option<[f64; 3]>,
+        radius: f64,
+        material: String,
+    },
+    Quad {
+        q: [f64; 3],
+        u: [f64; 3],
+        v: [f64; 3],
+        material: String,
+    },
+    Triangle {
+        a: [f64; 3],
+        b: [f64; 3],
+        c: [f64; 3],
+        material: String,
+    },
+    Mesh {
+        path: PathBuf,
+        material: Option<String>,
+        crease_angle: Option<f64>,
+        #[serde(default = "default_skip_degenerate_triangles")]
+        skip_degenerate_triangles: bool,
+    },
+    Box {
+        min: [f64; 3],
+        max: [f64; 3],
+        material: String,
+    },
+    Medium {
+        // Object table for the volume's shape, its material defaults to the medium's
+        boundary: Value,
+        density: f64,
+        material: String,
+    },
+    Volume {
+        min: [f64; 3],
+        max: [f64; 3],
+        // Density where the field is densest
+        density: f64,
+        field: FieldDescription,
+        material: String,
+    },
+}
+
+fn default_skip_degenerate_triangles() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum FieldDescription {
+    Grid {
+        path: PathBuf,
+        resolution: [usize; 3],
+    },
+    Noise {
+        scale: f64,
+    },
+}
+
+impl ObjectDescription {
+    fn build(
+        &self,
+        materials: &HashMap<String, Material>,
+        base_directory: &Path,
+    ) -> Result<WorldObject, String> {
+        let material = |name: &String| {
+            materials
+                .get(name)
+                .cloned()
+                .ok_or_else(|| format!("unknown material `{}`", name))
+        };
+
+        let object = match self {
+            ObjectDescription::Sphere {
+                center,
+                end_center,
+                radius,
+                material: name,
+            } => WorldObject::Sphere(Sphere::new_moving(
+                vector(*center),
+                vector(end_center.unwrap_or(*center)),
+                *radius,
+                material(name)?,
+            )),
+            ObjectDescription::Quad {
+                q,
+                u,
+                v,
+                material: name,
+            } => WorldObject::Quad(Quad::new_quad(
+                vector(*q),
+                vector(*u),
+                vector(*v),
+                material(name)?,
+            )),
+            ObjectDescription::Triangle {
+                a,
+                b,
+                c,
+                material: name,
+            } => WorldObject::Quad(Quad::new_triangle(
+                vector(*a),
+                vector(*b),
+                vector(*c),
+                material(name)?,
+            )),
+            ObjectDescription::Mesh {
+                path,
+                material: name,
+                crease_angle,
+                skip_degenerate_triangles,
+            } => {
+                // Without a material the mesh uses the ones from its MTL files
+                let mut options = MeshOptions::default();
+                if let Some(name) = name {
+                    options = options.material(material(name)?);
+                }
+                if let Some(crease_angle) = crease_angle {
+                    options = options.crease_angle(*crease_angle);
+                }
+                if !*skip_degenerate_triangles {
+                    options = options.reject_degenerate_triangles();
+                }
+                let path = base_directory.join(path);
+                let mesh =
+                    Mesh::from_file_with_options(&path, options).map_err(|e| e.to_string())?;
+                if mesh.skipped_triangles() > 0 {
+                    eprintln!(
+                        "warning: {}: skipped {} degenerate triangles",
+                        path.display(),
+                        mesh.skipped_triangles()
+                    );
+                }
+                WorldObject::Mesh(mesh)
+            }
+            ObjectDescription::Box {
+                min,
+                max,
+                material: name,
+            } => quad_box(vector(*min), vector(*max), material(name)?),
+            ObjectDescription::Medium {
+                boundary,
+                density,
+                material: name,
+            } => {
+                if *density <= 0.0 {
+                    return Err(format!("medium density must be positive, got {}", density));
+                }
+                let Material::Isotropic(phase_function) = material(name)? else {
+                    return Err(format!("medium material `{}` is not isotropic", name));
+                };
+
+                let mut boundary = boundary.clone();
+                if let Some(table) = boundary.as_table_mut() {
+                    table
+                        .entry("material")
+                        .or_insert_with(|| Value::String(name.clone()));
+                }
+                let boundary = ObjectDescription::deserialize(boundary)
+                    .map_err(|e| format!("in boundary: {}", e.message()))?
+                    .build(materials, base_directory)?;
+
+                WorldObject::ConstantMedium(ConstantMedium::new(
+                    Arc::new(boundary),
+                    *density,
+                    phase_function,
+                ))
+            }
+            ObjectDescription::Volume {
+                min,
+                max,
+                density,
+                field,
+                material: name,
+            } => {
+                if *density <= 0.0 {
+                    return Err(format!("volume density must be positive, got {}", density));
+                }
+                let Material::Isotropic(phase_function) = material(name)? else {
+                    return Err(format!("volume material `{}` is not isotropic", name));
+                };
+
+                let field = match field {
+                    FieldDescription::Grid { path, resolution } => DensityField::Grid(
+                        VoxelGrid::from_file(&base_directory.join(path), *resolution)
+                            .map_err(|e| e.to_string())?,
+                    ),
+                    FieldDescription::Noise { scale } => {
+                        DensityField::Noise(NoiseField::new(*scale))
+                    }
+                };
+
+                WorldObject::HeterogeneousMedium(HeterogeneousMedium::new(
+                    vector(*min),
+                    vector(*max),
+                    field,
+                    *density,
+                    phase_function,
+                ))
+            }
+        };
+
+        Ok(object)
+    }
+}
+
+fn vector(array: [f64; 3]) -> Vector3 {
+    Vector3::new(array[0], array[1], array[2])
+}
@@ -0,0 +1,241 @@
+use std::f64::consts::PI;
+
+use crate::{
+    onb::OrthonormalBasis,
+    tonemap::luminance,
+    util::random_unit_float,
+    vec::{dot_product, unit_vector, Vector3},
+};
+
+// Preetham's luminance is in kcd/m², this lets a white wall in full sun come out near 1
+const LUMINANCE_SCALE: f64 = 0.04;
+// Luminance of the sun before the atmosphere attenuates it, in kcd/m²
+const SUN_LUMINANCE: f64 = 2.0e6;
+// Half of the sun's apparent diameter
+const SUN_ANGULAR_RADIUS: f64 = 0.265;
+// Red, green and blue wavelengths in micrometers, for the sun's extinction
+const WAVELENGTHS: [f64; 3] = [0.68, 0.55, 0.44];
+
+// Rows and columns the upper hemisphere is split into to integrate the sky's light
+const INTEGRATION_ROWS: usize = 32;
+const INTEGRATION_COLUMNS: usize = 64;
+
+// Daylight from the analytic sky model of Preetham et al., "A Practical Analytic Model
+// for Daylight" (1999), with a sun disk. Below the horizon the ground reflects the light
+// of the sky and sun with its albedo.
+pub struct Sky {
+    sun_direction: Vector3,
+    intensity: f64,
+    // Perez coefficients A to E for the luminance Y and the chromaticities x and y
+    coefficients: [[f64; 5]; 3],
+    // Y, x and y at the zenith, divided by the Perez function there
+    zenith: [f64; 3],
+    sun_radiance: Vector3,
+    cos_sun_radius: f64,
+    ground_radiance: Vector3,
+    // Chance of sampling the sun rather than the whole sphere, by their share of the light
+    sun_probability: f64,
+}
+
+impl Sky {
+    // Turbidity is the haziness of the atmosphere, from about 2 for a clear sky to 10
+    pub fn new(sun_direction: Vector3, turbidity: f64, ground_albedo: Vector3) -> Self {
+        let sun_direction = unit_vector(sun_direction);
+        // The model breaks down once the sun sets, so it stays at the horizon
+        let theta_sun = sun_direction.y().clamp(0.0, 1.0).acos();
+        let t = turbidity;
+
+        let coefficients = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_sun);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let chromaticity = |matrix: [[f64; 4]; 3]| {
+            let turbidities = [t * t, t, 1.0];
+            let angles = [theta_sun.powi(3), theta_sun.powi(2), theta_sun, 1.0];
+            (0..3)
+                .map(|row| {
+                    turbidities[row]
+                        * (0..4)
+                            .map(|column| matrix[row][column] * angles[column])
+                            .sum::<f64>()
+                })
+                .sum::<f64>()
+        };
+        let zenith_x = chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let zenith_y = chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+
+        let zenith_values = [zenith_luminance, zenith_x, zenith_y];
+        let zenith = std::array::from_fn(|channel| {
+            zenith_values[channel] / perez(&coefficients[channel], 1.0, theta_sun)
+        });
+
+        let cos_sun_radius = SUN_ANGULAR_RADIUS.to_radians().cos();
+        let sun_solid_angle = 2.0 * PI * (1.0 - cos_sun_radius);
+        let sun_radiance = if sun_direction.y() > 0.0 {
+            sun_transmittance(theta_sun, turbidity) * SUN_LUMINANCE * LUMINANCE_SCALE
+        } else {
+            Vector3::zero()
+        };
+
+        let mut sky = Self {
+            sun_direction,
+            intensity: 1.0,
+            coefficients,
+            zenith,
+            sun_radiance,
+            cos_sun_radius,
+            ground_radiance: Vector3::zero(),
+            sun_probability: 0.0,
+        };
+
+        // Light the sky sends to the ground and into all directions, by the midpoint rule
+        let mut irradiance = Vector3::zero();
+        let mut sky_power = 0.0;
+        for row in 0..INTEGRATION_ROWS {
+            let theta = (row as f64 + 0.5) / INTEGRATION_ROWS as f64 * PI / 2.0;
+            let solid_angle = theta.sin()
+                * (PI / 2.0 / INTEGRATION_ROWS as f64)
+                * (2.0 * PI / INTEGRATION_COLUMNS as f64);
+            for column in 0..INTEGRATION_COLUMNS {
+                let phi = (column as f64 + 0.5) / INTEGRATION_COLUMNS as f64 * 2.0 * PI;
+                let direction = Vector3::new(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                let radiance = sky.sky_radiance(direction);
+                irradiance = irradiance + radiance * (theta.cos() * solid_angle);
+                sky_power += luminance(radiance) * solid_angle;
+            }
+        }
+        irradiance = irradiance + sun_radiance * (sun_solid_angle * sun_direction.y().max(0.0));
+        sky.ground_radiance = ground_albedo * irradiance / PI;
+
+        let ground_power = luminance(sky.ground_radiance) * 2.0 * PI;
+        let sun_power = luminance(sun_radiance) * sun_solid_angle;
+        sky.sun_probability = sun_power / (sun_power + sky_power + ground_power);
+
+        sky
+    }
+
+    pub fn intensity(mut self, intensity: f64) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    // Radiance arriving from the given direction
+    pub fn value(&self, direction: Vector3) -> Vector3 {
+        let direction = unit_vector(direction);
+        if direction.y() < 0.0 {
+            return self.ground_radiance * self.intensity;
+        }
+
+        let mut radiance = self.sky_radiance(direction);
+        if dot_product(direction, self.sun_direction) >= self.cos_sun_radius {
+            radiance = radiance + self.sun_radiance;
+        }
+        radiance * self.intensity
+    }
+
+    // Samples the sun disk or, for the rest of the light, the whole sphere uniformly
+    pub fn sample_direction(&self) -> Option<Vector3> {
+        if random_unit_float() < self.sun_probability {
+            let z = 1.0 + random_unit_float() * (self.cos_sun_radius - 1.0);
+            let phi = 2.0 * PI * random_unit_float();
+            let sin_theta = (1.0 - z * z).sqrt();
+            let local = Vector3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, z);
+            Some(OrthonormalBasis::new(self.sun_direction).transform(local))
+        } else {
+            Some(Vector3::random_unit_vector())
+        }
+    }
+
+    // Density of sample_direction() per unit solid angle
+    pub fn pdf_value(&self, direction: Vector3) -> f64 {
+        let mut pdf = (1.0 - self.sun_probability) / (4.0 * PI);
+        if dot_product(unit_vector(direction), self.sun_direction) >= self.cos_sun_radius {
+            pdf += self.sun_probability / (2.0 * PI * (1.0 - self.cos_sun_radius));
+        }
+        pdf
+    }
+
+    // The sky without the sun, for directions above the horizon
+    fn sky_radiance(&self, direction: Vector3) -> Vector3 {
+        // Keeps the Perez function finite at the horizon
+        let cos_theta = direction.y().max(0.001);
+        let gamma = dot_product(direction, self.sun_direction)
+            .clamp(-1.0, 1.0)
+            .acos();
+
+        let [luminance, x, y]: [f64; 3] = std::array::from_fn(|channel| {
+            self.zenith[channel] * perez(&self.coefficients[channel], cos_theta, gamma)
+        });
+        xyy_to_rgb(x, y, luminance * LUMINANCE_SCALE)
+    }
+}
+
+// Relative sky brightness for a view at an angle from the zenith and gamma from the sun
+fn perez([a, b, c, d, e]: &[f64; 5], cos_theta: f64, gamma: f64) -> f64 {
+    (1.0 + a * (b / cos_theta).exp()) * (1.0 + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+}
+
+// Fraction of the sunlight that makes it through the air, from Rayleigh scattering by
+// molecules and Ångström's formula for aerosols, following the paper's appendix
+fn sun_transmittance(theta_sun: f64, turbidity: f64) -> Vector3 {
+    let degrees = theta_sun.to_degrees();
+    let air_mass = 1.0 / (theta_sun.cos() + 0.15 * (93.885 - degrees).powf(-1.253));
+    let beta = 0.04608 * turbidity - 0.04586;
+
+    let [r, g, b] = WAVELENGTHS.map(|wavelength: f64| {
+        let rayleigh = 0.008735 * wavelength.powf(-4.08);
+        let aerosol = beta * wavelength.powf(-1.3);
+        (-(rayleigh + aerosol) * air_mass).exp()
+    });
+    Vector3::new(r, g, b)
+}
+
+// CIE xyY to linear sRGB, negative components from out of gamut colors are dropped
+fn xyy_to_rgb(x: f64, y: f64, luminance: f64) -> Vector3 {
+    if y <= 0.0 {
+        return Vector3::zero();
+    }
+
+    let big_x = x / y * luminance;
+    let big_z = (1.0 - x - y) / y * luminance;
+
+    Vector3::new(
+        (3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z).max(0.0),
+        (-0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z).max(0.0),
+        (0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z).max(0.0),
+    )
+}
@@ -1,12 +1,18 @@
+use std::f64::consts::PI;
+
 use crate::{
     aabb::AABB,
     material::Material,
-    ray::{HitRecord, Hittable, Interval, Ray},
+    onb::OrthonormalBasis,
+    ray::{HitRecord, Hittable, Interval, Ray, Sampleable},
+    util::random_unit_float,
     vec::{dot_product, Vector3},
 };
 
 pub struct Sphere {
+    // Center at time 0, it moves by `motion` per unit of time
     center: Vector3,
+    motion: Vector3,
     radius: f64,
     radius_squared: f64,
     material: Material,
@@ -15,11 +21,21 @@ pub struct Sphere {
 
 impl Sphere {
     pub fn new(center: Vector3, radius: f64, material: Material) -> Self {
+        Self::new_moving(center, center, radius, material)
+    }
+
+    // Moves in a straight line from `start` at time 0 to `end` at time 1
+    pub fn new_moving(start: Vector3, end: Vector3, radius: f64, material: Material) -> Self {
         let radius_vector = Vector3::new(radius, radius, radius);
-        let bounding_box = AABB::from_points(center - radius_vector, center + radius_vector);
+        // Bounds the whole path, so the BVH finds the sphere at any time in between
+        let bounding_box = AABB::from_bounding_boxes(
+            AABB::from_points(start - radius_vector, start + radius_vector),
+            AABB::from_points(end - radius_vector, end + radius_vector),
+        );
 
         Self {
-            center,
+            center: start,
+            motion: end - start,
             radius,
             radius_squared: radius * radius,
             material,
@@ -27,16 +43,24 @@ impl Sphere {
         }
     }
 
-    pub fn center(&self) -> Vector3 {
-        self.center
+    pub fn center(&self, time: f64) -> Vector3 {
+        self.center + self.motion * time
     }
 
     pub fn radius(&self) -> f64 {
         self.radius
     }
 
-    pub fn material(&self) -> Material {
-        self.material
+    pub fn material(&self) -> &Material {
+        &self.material
+    }
+
+    // Maps a point on the unit sphere to (u, v), with u around the y axis from x = -1
+    // and v from the bottom to the top pole
+    fn surface_coordinates(point: Vector3) -> (f64, f64) {
+        let theta = (-point.y()).acos();
+        let phi = (-point.z()).atan2(point.x()) + PI;
+        (phi / (2.0 * PI), theta / PI)
     }
 }
 
@@ -45,8 +69,9 @@ impl Hittable for Sphere {
         self.bounding_box
     }
 
-    fn hit(&self, ray: &Ray, t: &Interval) -> Option<HitRecord> {
-        let oc = self.center() - ray.origin();
+    fn hit(&self, ray: &Ray, t: &Interval) -> Option<HitRecord<'_>> {
+        let center = self.center(ray.time());
+        let oc = center - ray.origin();
         let a = ray.direction_length_squared(); // dot(dir, dir)
         let h = dot_product(ray.direction(), oc);
         let c = oc.length_squared() - self.radius_squared;
@@ -61,17 +86,68 @@ impl Hittable for Sphere {
         for root in [(h - sqrtd) / a, (h + sqrtd) / a] {
             if t.surrounds(root) {
                 let point = ray.at(root);
-                let outward_normal = (point - self.center()) / self.radius();
+                let outward_normal = (point - center) / self.radius();
                 let front_face = dot_product(ray.direction(), outward_normal) < 0.0;
                 let normal = if front_face {
                     outward_normal
                 } else {
                     -outward_normal
                 };
-                return Some(HitRecord::new(point, normal, self.material, root, front_face));
+                let (u, v) = Sphere::surface_coordinates(outward_normal);
+                return Some(HitRecord::new(
+                    point,
+                    normal,
+                    &self.material,
+                    root,
+                    front_face,
+                    u,
+                    v,
+                ));
             }
         }
 
         None
     }
 }
+
+impl Sampleable for Sphere {
+    // Uniformly samples the cone of directions the sphere covers as seen from the origin
+    fn sample_direction(&self, origin: Vector3, time: f64) -> Option<Vector3> {
+        let direction = self.center(time) - origin;
+        let distance_squared = direction.length_squared();
+        if distance_squared <= self.radius_squared {
+            return None;
+        }
+
+        let cos_theta_max = (1.0 - self.radius_squared / distance_squared).sqrt();
+
+        let r1 = random_unit_float();
+        let r2 = random_unit_float();
+        let z = 1.0 + r2 * (cos_theta_max - 1.0);
+        let phi = 2.0 * PI * r1;
+        let sin_theta = (1.0 - z * z).sqrt();
+
+        let local = Vector3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, z);
+        Some(OrthonormalBasis::new(direction).transform(local))
+    }
+
+    fn pdf_value(&self, origin: Vector3, direction: Vector3, time: f64) -> f64 {
+        let ray = Ray::with_time(origin, direction, time);
+        if self
+            .hit(&ray, &Interval::new(0.001, f64::INFINITY))
+            .is_none()
+        {
+            return 0.0;
+        }
+
+        let distance_squared = (self.center(time) - origin).length_squared();
+        if distance_squared <= self.radius_squared {
+            return 0.0;
+        }
+
+        let cos_theta_max = (1.0 - self.radius_squared / distance_squared).sqrt();
+        let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
+
+        1.0 / solid_angle
+    }
+}
@@ -0,0 +1,70 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use crate::{
+    error::TracerError,
+    mesh::{Corner, Face, MeshModel},
+    vec::Vector3,
+};
+
+const HEADER_SIZE: usize = 84;
+// Normal, three vertices and a 2 byte attribute count
+const TRIANGLE_SIZE: usize = 50;
+
+// Default crease angle in degrees for STL meshes, which are mostly hard edged CAD parts
+pub(crate) const STL_CREASE_ANGLE: f64 = 30.0;
+
+// Reads a binary STL file. Its vertices are welded by position so that normals can be
+// generated across triangles, the facet normals in the file are ignored.
+pub(crate) fn load_stl(path: &Path) -> Result<MeshModel, TracerError> {
+    let bytes = fs::read(path).map_err(|e| TracerError::io(path, e))?;
+
+    // ASCII files start with `solid`, but so do some binary ones, so the size decides
+    let expected_size = bytes.get(80..HEADER_SIZE).map(|count| {
+        let count = u32::from_le_bytes(count.try_into().unwrap()) as usize;
+        HEADER_SIZE + count * TRIANGLE_SIZE
+    });
+    let Some(size) = expected_size.filter(|&size| size <= bytes.len()) else {
+        let message = if bytes.starts_with(b"solid") {
+            "ASCII STL files are not supported, only binary ones".to_string()
+        } else {
+            "the file is shorter than its triangle count".to_string()
+        };
+        return Err(TracerError::invalid_data(path, message));
+    };
+
+    let mut model = MeshModel::default();
+    let mut vertex_indices: HashMap<[u32; 3], usize> = HashMap::new();
+
+    for triangle in bytes[HEADER_SIZE..size].chunks_exact(TRIANGLE_SIZE) {
+        let corners = [12, 24, 36].map(|offset| {
+            // Adding zero turns -0.0 into 0.0, so both weld together
+            let coordinates = [0, 4, 8].map(|component| {
+                let start = offset + component;
+                f32::from_le_bytes(triangle[start..start + 4].try_into().unwrap()) + 0.0
+            });
+
+            let position = *vertex_indices
+                .entry(coordinates.map(f32::to_bits))
+                .or_insert_with(|| {
+                    let [x, y, z] = coordinates.map(|coordinate| coordinate as f64);
+                    model.positions.push(Vector3::new(x, y, z));
+                    model.positions.len() - 1
+                });
+
+            Corner {
+                position,
+                texture: None,
+                normal: None,
+                color: None,
+            }
+        });
+
+        model.faces.push(Face {
+            corners,
+            material: None,
+            line: None,
+        });
+    }
+
+    Ok(model)
+}
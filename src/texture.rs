@@ -0,0 +1,196 @@
+use std::{path::Path, sync::Arc};
+
+use image::ImageResult;
+
+use crate::{
+    perlin::Perlin,
+    ray::{HitRecord, Interval},
+    tonemap::srgb_to_linear,
+    vec::Vector3,
+};
+
+#[derive(Debug, Clone)]
+pub enum Texture {
+    Solid(SolidColor),
+    Checker(Checker),
+    Image(ImageTexture),
+    Noise(NoiseTexture),
+}
+
+impl Texture {
+    pub fn value(&self, u: f64, v: f64, point: Vector3) -> Vector3 {
+        match self {
+            Texture::Solid(solid) => solid.value(u, v, point),
+            Texture::Checker(checker) => checker.value(u, v, point),
+            Texture::Image(image) => image.value(u, v, point),
+            Texture::Noise(noise) => noise.value(u, v, point),
+        }
+    }
+
+    // Looks up the hit's surface coordinates, tinted by its vertex color if there is one
+    pub fn value_at_hit(&self, hit_record: &HitRecord) -> Vector3 {
+        let value = self.value(hit_record.u(), hit_record.v(), hit_record.point());
+        match hit_record.vertex_color() {
+            Some(color) => value * color,
+            None => value,
+        }
+    }
+}
+
+impl From<Vector3> for Texture {
+    fn from(color: Vector3) -> Self {
+        Texture::Solid(SolidColor::new(color))
+    }
+}
+
+// Grey, for material parameters that are a single number
+impl From<f64> for Texture {
+    fn from(value: f64) -> Self {
+        Texture::Solid(SolidColor::new(Vector3::new(value, value, value)))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SolidColor {
+    color: Vector3,
+}
+
+impl SolidColor {
+    pub fn new(color: Vector3) -> Self {
+        Self { color }
+    }
+
+    pub fn value(&self, _u: f64, _v: f64, _point: Vector3) -> Vector3 {
+        self.color
+    }
+}
+
+// Alternates between two textures in a 3D grid of cubes, independent of the surface (u, v)
+#[derive(Debug, Clone)]
+pub struct Checker {
+    inverse_scale: f64,
+    even: Arc<Texture>,
+    odd: Arc<Texture>,
+}
+
+impl Checker {
+    pub fn new(scale: f64, even: Texture, odd: Texture) -> Self {
+        Self {
+            inverse_scale: 1.0 / scale,
+            even: Arc::new(even),
+            odd: Arc::new(odd),
+        }
+    }
+
+    pub fn value(&self, u: f64, v: f64, point: Vector3) -> Vector3 {
+        let x = (self.inverse_scale * point.x()).floor() as i64;
+        let y = (self.inverse_scale * point.y()).floor() as i64;
+        let z = (self.inverse_scale * point.z()).floor() as i64;
+
+        if (x + y + z).rem_euclid(2) == 0 {
+            self.even.value(u, v, point)
+        } else {
+            self.odd.value(u, v, point)
+        }
+    }
+}
+
+// Image looked up by surface (u, v), stored as linear radiance
+#[derive(Debug, Clone)]
+pub struct ImageTexture {
+    width: u32,
+    height: u32,
+    pixels: Arc<Vec<Vector3>>,
+}
+
+impl ImageTexture {
+    // Loads a PNG or JPEG from disk, converting from sRGB to linear
+    pub fn from_file(path: &Path) -> ImageResult<Self> {
+        Self::load(path, srgb_to_linear)
+    }
+
+    // Loads an image that holds data rather than colors, such as a roughness map, as is
+    pub fn from_linear_file(path: &Path) -> ImageResult<Self> {
+        Self::load(path, |value| value)
+    }
+
+    fn load(path: &Path, decode: impl Fn(f64) -> f64) -> ImageResult<Self> {
+        let image = image::open(path)?.into_rgb8();
+        let (width, height) = image.dimensions();
+
+        let pixels = image
+            .pixels()
+            .map(|pixel| {
+                Vector3::new(
+                    decode(pixel[0] as f64 / 255.0),
+                    decode(pixel[1] as f64 / 255.0),
+                    decode(pixel[2] as f64 / 255.0),
+                )
+            })
+            .collect();
+
+        Ok(Self::new(width, height, pixels))
+    }
+
+    // Pixels are row-major starting at the top left
+    pub fn new(width: u32, height: u32, pixels: Vec<Vector3>) -> Self {
+        assert_eq!(
+            pixels.len(),
+            width as usize * height as usize,
+            "pixel count does not match image dimensions"
+        );
+        Self {
+            width,
+            height,
+            pixels: Arc::new(pixels),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn value(&self, u: f64, v: f64, _point: Vector3) -> Vector3 {
+        if self.pixels.is_empty() {
+            // Debug cyan, makes missing image data obvious
+            return Vector3::new(0.0, 1.0, 1.0);
+        }
+
+        let interval = Interval::new(0.0, 1.0);
+        let u = interval.clamp(u);
+        // Flip v, image rows start at the top
+        let v = 1.0 - interval.clamp(v);
+
+        let i = ((u * self.width as f64) as u32).min(self.width - 1);
+        let j = ((v * self.height as f64) as u32).min(self.height - 1);
+
+        self.pixels[j as usize * self.width as usize + i as usize]
+    }
+}
+
+// Marble-like pattern from phase-shifted Perlin turbulence
+#[derive(Debug, Clone)]
+pub struct NoiseTexture {
+    perlin: Arc<Perlin>,
+    scale: f64,
+    color: Vector3,
+}
+
+impl NoiseTexture {
+    pub fn new(scale: f64, color: Vector3) -> Self {
+        Self {
+            perlin: Arc::new(Perlin::new()),
+            scale,
+            color,
+        }
+    }
+
+    pub fn value(&self, _u: f64, _v: f64, point: Vector3) -> Vector3 {
+        let phase = self.scale * point.z() + 10.0 * self.perlin.turbulence(point, 7);
+        self.color * 0.5 * (1.0 + phase.sin())
+    }
+}
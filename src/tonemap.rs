@@ -0,0 +1,111 @@
+use crate::{ray::Interval, vec::Vector3};
+
+#[derive(Debug, Clone, Copy)]
+pub enum ToneMapping {
+    // Clamp radiance to [0, 1], anything brighter is lost
+    Clamp,
+    Reinhard,
+    // Reinhard that maps the given luminance to pure white instead of infinity
+    ExtendedReinhard { white_point: f64 },
+    // Narkowicz's fit of the ACES filmic curve
+    Aces,
+}
+
+// Maps linear radiance to displayable sRGB pixels
+#[derive(Debug, Clone, Copy)]
+pub struct ToneMapper {
+    operator: ToneMapping,
+    exposure: f64,
+}
+
+impl ToneMapper {
+    // Exposure is given in stops, each stop doubles the radiance before mapping
+    pub fn new(operator: ToneMapping, exposure: f64) -> Self {
+        Self { operator, exposure }
+    }
+
+    pub fn operator(&self) -> ToneMapping {
+        self.operator
+    }
+
+    pub fn exposure(&self) -> f64 {
+        self.exposure
+    }
+
+    // Returns the tone mapped color in linear [0, 1]
+    pub fn map(&self, color: Vector3) -> Vector3 {
+        let color = color * self.exposure.exp2();
+
+        let mapped = match self.operator {
+            ToneMapping::Clamp => color,
+            ToneMapping::Reinhard => scale_luminance(color, |l| l / (1.0 + l)),
+            ToneMapping::ExtendedReinhard { white_point } => {
+                let white_squared = white_point * white_point;
+                scale_luminance(color, |l| l * (1.0 + l / white_squared) / (1.0 + l))
+            }
+            ToneMapping::Aces => Vector3::new(aces(color.x()), aces(color.y()), aces(color.z())),
+        };
+
+        let interval = Interval::new(0.0, 1.0);
+        Vector3::new(
+            interval.clamp(mapped.x()),
+            interval.clamp(mapped.y()),
+            interval.clamp(mapped.z()),
+        )
+    }
+
+    // Tone maps, applies the sRGB transfer curve and packs RGB into 1 u32
+    pub fn to_pixel(&self, color: Vector3) -> u32 {
+        let mapped = self.map(color);
+
+        let (r, g, b) = (
+            (linear_to_srgb(mapped.x()) * 255.0).round() as u32,
+            (linear_to_srgb(mapped.y()) * 255.0).round() as u32,
+            (linear_to_srgb(mapped.z()) * 255.0).round() as u32,
+        );
+
+        (r << 16) | (g << 8) | b
+    }
+}
+
+impl Default for ToneMapper {
+    fn default() -> Self {
+        Self::new(ToneMapping::Clamp, 0.0)
+    }
+}
+
+// Rec. 709 relative luminance
+pub fn luminance(color: Vector3) -> f64 {
+    0.2126 * color.x() + 0.7152 * color.y() + 0.0722 * color.z()
+}
+
+// sRGB opto-electronic transfer function, expects a value in [0, 1]
+pub fn linear_to_srgb(value: f64) -> f64 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+pub fn srgb_to_linear(value: f64) -> f64 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// Applies a curve to the luminance only, which keeps hue intact
+fn scale_luminance(color: Vector3, curve: impl Fn(f64) -> f64) -> Vector3 {
+    let l = luminance(color);
+    if l <= 0.0 {
+        return Vector3::zero();
+    }
+    color * (curve(l) / l)
+}
+
+fn aces(x: f64) -> f64 {
+    let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+    (x * (a * x + b)) / (x * (c * x + d) + e)
+}
@@ -0,0 +1,94 @@
+use crate::{ray::Ray, vec::Vector3};
+
+// Ray-triangle test that never lets a ray slip through the shared edge of two
+// triangles, see Woop et al. 2013, "Watertight Ray/Triangle Intersection".
+// The setup only depends on the ray, so it is done once per mesh traversal.
+pub struct WatertightRay {
+    origin: Vector3,
+    // Axes permuted so z is the largest direction component
+    kx: i32,
+    ky: i32,
+    kz: i32,
+    // Shear that aligns the direction with z, and 1 / direction[kz]
+    shear_x: f64,
+    shear_y: f64,
+    shear_z: f64,
+}
+
+impl WatertightRay {
+    pub fn new(ray: &Ray) -> Self {
+        let direction = ray.direction();
+        let absolute = Vector3::new(
+            direction.x().abs(),
+            direction.y().abs(),
+            direction.z().abs(),
+        );
+
+        let kz = if absolute.x() > absolute.y() {
+            if absolute.x() > absolute.z() {
+                0
+            } else {
+                2
+            }
+        } else if absolute.y() > absolute.z() {
+            1
+        } else {
+            2
+        };
+        let mut kx = (kz + 1) % 3;
+        let mut ky = (kx + 1) % 3;
+        // Keep the winding order when the dominant component is negative
+        if direction.axis(kz) < 0.0 {
+            std::mem::swap(&mut kx, &mut ky);
+        }
+
+        Self {
+            origin: ray.origin(),
+            kx,
+            ky,
+            kz,
+            shear_x: direction.axis(kx) / direction.axis(kz),
+            shear_y: direction.axis(ky) / direction.axis(kz),
+            shear_z: 1.0 / direction.axis(kz),
+        }
+    }
+
+    // Ray parameter t and the barycentric weights of a, b and c at the hit.
+    // Points on an edge count as inside, so neighbours may both report it.
+    pub fn intersect(&self, a: Vector3, b: Vector3, c: Vector3) -> Option<(f64, [f64; 3])> {
+        let (a, b, c) = (a - self.origin, b - self.origin, c - self.origin);
+
+        // Project into the ray's space, where it points along z from the origin
+        let (ax, ay) = self.shear(a);
+        let (bx, by) = self.shear(b);
+        let (cx, cy) = self.shear(c);
+
+        // Scaled barycentric coordinates, as signed edge functions
+        let u = cx * by - cy * bx;
+        let v = ax * cy - ay * cx;
+        let w = bx * ay - by * ax;
+
+        if (u < 0.0 || v < 0.0 || w < 0.0) && (u > 0.0 || v > 0.0 || w > 0.0) {
+            return None;
+        }
+
+        let determinant = u + v + w;
+        if determinant == 0.0 {
+            return None;
+        }
+
+        let t = (u * self.shear_z * a.axis(self.kz)
+            + v * self.shear_z * b.axis(self.kz)
+            + w * self.shear_z * c.axis(self.kz))
+            / determinant;
+
+        Some((t, [u / determinant, v / determinant, w / determinant]))
+    }
+
+    fn shear(&self, point: Vector3) -> (f64, f64) {
+        (
+            point.axis(self.kx) - self.shear_x * point.axis(self.kz),
+            point.axis(self.ky) - self.shear_y * point.axis(self.kz),
+        )
+    }
+}
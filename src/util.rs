@@ -27,3 +27,10 @@ pub fn random_color_range(min: f64, max: f64) -> Vector3 {
         random_float(min, max),
     )
 }
+
+// Random index in 0..length
+pub fn random_index(length: usize) -> usize {
+    let mut rng = rand::thread_rng();
+
+    rng.gen_range(0..length)
+}
@@ -1,21 +1,115 @@
 use std::sync::Arc;
 
 use crate::{
-    bvh::BVHNode,
+    bvh::BVH,
+    environment::Environment,
     ray::{HitRecord, Hittable, Interval, Ray, WorldObject},
+    util::random_index,
+    vec::Vector3,
 };
 
 pub struct World {
-    node: BVHNode,
+    bvh: BVH,
+    // Emissive spheres, quads and mesh triangles, sampled directly for lighting
+    lights: Vec<Arc<WorldObject>>,
+    // Heterogeneous media, which shadow rays see through by their transmittance
+    media: Vec<Arc<WorldObject>>,
+    // Lights the scene from all around, replacing the camera's background color
+    environment: Option<Environment>,
 }
 
 impl World {
     pub fn new(objects: Vec<Arc<WorldObject>>) -> Self {
-        let node = BVHNode::new(objects);
-        Self { node }
+        let lights = objects.iter().flat_map(|object| object.lights()).collect();
+        let media = objects
+            .iter()
+            .filter(|object| matches!(object.as_ref(), WorldObject::HeterogeneousMedium(_)))
+            .cloned()
+            .collect();
+        let bvh = BVH::new(objects);
+        Self {
+            bvh,
+            lights,
+            media,
+            environment: None,
+        }
     }
 
-    pub fn hit(&self, ray: &Ray, t: &Interval) -> Option<HitRecord> {
-        self.node.hit(ray, t)
+    pub fn with_environment(mut self, environment: Environment) -> Self {
+        self.environment = Some(environment);
+        self
+    }
+
+    pub fn environment(&self) -> Option<&Environment> {
+        self.environment.as_ref()
+    }
+
+    pub fn hit(&self, ray: &Ray, t: &Interval) -> Option<HitRecord<'_>> {
+        self.bvh.hit(ray, t)
+    }
+
+    // Closest hit that isn't inside a heterogeneous medium, along with the fraction of
+    // light that makes it from there, or from the environment, through the media in front
+    pub fn hit_through_media(&self, ray: &Ray, t: &Interval) -> (Option<HitRecord<'_>>, f64) {
+        let hit = self
+            .bvh
+            .hit_with(ray, t, |object, t| match object.as_ref() {
+                WorldObject::HeterogeneousMedium(_) => None,
+                object => object.hit(ray, t),
+            });
+
+        let in_front = Interval::new(t.min(), hit.as_ref().map_or(t.max(), |hit| hit.t()));
+        let transmittance = self
+            .media
+            .iter()
+            .map(|medium| match medium.as_ref() {
+                WorldObject::HeterogeneousMedium(medium) => medium.transmittance(ray, &in_front),
+                _ => 1.0,
+            })
+            .product();
+
+        (hit, transmittance)
+    }
+
+    pub fn lights(&self) -> &[Arc<WorldObject>] {
+        &self.lights
+    }
+
+    // Lights that can be sampled, counting the environment as one
+    fn light_count(&self) -> usize {
+        self.lights.len() + usize::from(self.environment.is_some())
+    }
+
+    // Picks one light uniformly and samples a direction towards it, where it is at `time`
+    pub fn sample_light_direction(&self, origin: Vector3, time: f64) -> Option<Vector3> {
+        let count = self.light_count();
+        if count == 0 {
+            return None;
+        }
+
+        match self.lights.get(random_index(count)) {
+            Some(light) => light.sample_direction(origin, time),
+            None => self.environment.as_ref()?.sample_direction(),
+        }
+    }
+
+    // Density of sample_light_direction() producing the given direction, which
+    // includes the chance of it being generated by any of the lights
+    pub fn light_pdf_value(&self, origin: Vector3, direction: Vector3, time: f64) -> f64 {
+        let count = self.light_count();
+        if count == 0 {
+            return 0.0;
+        }
+
+        let mut sum: f64 = self
+            .lights
+            .iter()
+            .map(|light| light.pdf_value(origin, direction, time))
+            .sum();
+        if let Some(environment) = &self.environment {
+            sum += environment.pdf_value(direction);
+        }
+
+        sum / count as f64
     }
 }